@@ -0,0 +1,93 @@
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+
+use msg::order::*;
+
+//Splits the qty of an aggressor between the resting orders of one price level.
+//p_orders is the level queue keyed by priority, only the displayed qty of an order can be allocated.
+//Returns (priority_seq_, qty) in the order the fills are printed, the total is
+//min(p_qty, displayed qty of the level) and no entry has a zero qty.
+pub trait AllocationAlgorithm: Debug + Send + Sync {
+    fn allocate(&self, p_orders: &BTreeMap<u64, Order>, p_qty: Quantity) -> Vec<(u64, Quantity)>;
+}
+
+//Price/time, the oldest order is filled first
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Fifo;
+
+impl AllocationAlgorithm for Fifo {
+    fn allocate(&self, p_orders: &BTreeMap<u64, Order>, p_qty: Quantity) -> Vec<(u64, Quantity)> {
+        let mut allocations = Vec::new();
+        let mut remaining_qty = p_qty;
+        for (priority_seq, order) in p_orders {
+            if remaining_qty.is_zero() {
+                break;
+            }
+            let allocated_qty = remaining_qty.min(order.displayed_qty());
+            if allocated_qty.is_zero() {
+                continue;
+            }
+            remaining_qty -= allocated_qty;
+            allocations.push((*priority_seq, allocated_qty));
+        }
+        allocations
+    }
+}
+
+//Every order gets a share proportional to its displayed qty.
+//With top_order_priority_ the first order in the queue is filled before the pro-rata split,
+//shares below min_allocation_ are not given and, with the rounding leftover, go in time priority.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ProRata {
+    pub top_order_priority_: bool,
+    pub min_allocation_: Quantity,
+}
+
+impl AllocationAlgorithm for ProRata {
+    fn allocate(&self, p_orders: &BTreeMap<u64, Order>, p_qty: Quantity) -> Vec<(u64, Quantity)> {
+        //(priority_seq_, displayed qty, allocated qty) in time priority
+        let mut allocations: Vec<(u64, Quantity, Quantity)> = p_orders
+            .iter()
+            .filter(|(_, order)| !order.displayed_qty().is_zero())
+            .map(|(priority_seq, order)| (*priority_seq, order.displayed_qty(), Quantity::ZERO))
+            .collect();
+        let mut remaining_qty = p_qty;
+
+        let mut pro_rata_start = 0;
+        if self.top_order_priority_ {
+            if let Some(top_order) = allocations.first_mut() {
+                top_order.2 = remaining_qty.min(top_order.1);
+                remaining_qty -= top_order.2;
+                pro_rata_start = 1;
+            }
+        }
+
+        let total_qty: u128 = allocations[pro_rata_start..]
+            .iter()
+            .map(|allocation| allocation.1 .0 as u128)
+            .sum();
+        let pro_rata_qty = remaining_qty;
+        for allocation in &mut allocations[pro_rata_start..] {
+            let share = (allocation.1 .0 as u128 * pro_rata_qty.0 as u128)
+                .checked_div(total_qty)
+                .unwrap_or(0) as u64;
+            let share = Quantity(share).min(allocation.1);
+            if share >= self.min_allocation_ {
+                allocation.2 += share;
+                remaining_qty -= share;
+            }
+        }
+
+        for allocation in &mut allocations {
+            let leftover_qty = remaining_qty.min(allocation.1 - allocation.2);
+            allocation.2 += leftover_qty;
+            remaining_qty -= leftover_qty;
+        }
+
+        allocations
+            .into_iter()
+            .filter(|allocation| !allocation.2.is_zero())
+            .map(|(priority_seq, _, allocated_qty)| (priority_seq, allocated_qty))
+            .collect()
+    }
+}
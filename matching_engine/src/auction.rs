@@ -0,0 +1,177 @@
+use msg::order::*;
+
+use crate::error::EngineError;
+use crate::{EventSeq, Fill, Level, MatchingResult, OrderBook};
+
+//Equilibrium of a call auction, volume_ executes at price_ and imbalance_ is left unfilled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClearingPrice {
+    pub price_: Price,
+    pub volume_: Quantity,
+    pub imbalance_: Quantity,
+}
+
+impl ClearingPrice {
+    //Maximum executable volume, then minimum imbalance, then closest to the reference price.
+    //Candidates are visited from the lowest price so the lowest price wins a full tie.
+    fn is_better_than(&self, p_other: &ClearingPrice, p_reference_price: Option<Price>) -> bool {
+        if self.volume_ != p_other.volume_ {
+            return self.volume_ > p_other.volume_;
+        }
+        if self.imbalance_ != p_other.imbalance_ {
+            return self.imbalance_ < p_other.imbalance_;
+        }
+        match p_reference_price {
+            None => false,
+            Some(reference_price) => {
+                self.price_.0.abs_diff(reference_price.0)
+                    < p_other.price_.0.abs_diff(reference_price.0)
+            }
+        }
+    }
+}
+
+impl OrderBook {
+    //Every bid and ask price is a candidate, hidden reserve of iceberg orders takes part
+    pub(crate) fn clearing_price(&self, p_reference_price: Option<Price>) -> Option<ClearingPrice> {
+        let mut candidate_prices: Vec<Price> = self
+            .bids_
+            .keys()
+            .chain(self.asks_.keys())
+            .copied()
+            .collect();
+        candidate_prices.sort();
+        candidate_prices.dedup();
+
+        let mut best_clearing_price: Option<ClearingPrice> = None;
+        for price in candidate_prices {
+            let mut buy_qty = Quantity::ZERO;
+            for level in self.bids_.range(price..).map(|(_, level)| level) {
+                buy_qty += level.total_qty();
+            }
+            let mut sell_qty = Quantity::ZERO;
+            for level in self.asks_.range(..=price).map(|(_, level)| level) {
+                sell_qty += level.total_qty();
+            }
+
+            let volume = buy_qty.min(sell_qty);
+            if volume.is_zero() {
+                continue;
+            }
+            let candidate = ClearingPrice {
+                price_: price,
+                volume_: volume,
+                imbalance_: buy_qty.max(sell_qty) - volume,
+            };
+            let is_better = match &best_clearing_price {
+                None => true,
+                Some(best) => candidate.is_better_than(best, p_reference_price),
+            };
+            if is_better {
+                best_clearing_price = Some(candidate);
+            }
+        }
+        best_clearing_price
+    }
+
+    //Executes every crossing order at the clearing price in price/time priority,
+    //the book is left uncrossed and open for continuous trading
+    pub(crate) fn uncross(
+        &mut self,
+        p_reference_price: Option<Price>,
+    ) -> Result<Option<MatchingResult>, EngineError> {
+        self.trading_state_ = TradingState::Open;
+        let Some(clearing_price) = self.clearing_price(p_reference_price) else {
+            println!("Auction closed without a clearing price");
+            return Ok(None);
+        };
+        println!("Uncross at {:?}", clearing_price);
+
+        //buy orders are reported as the aggressor side of the auction trades. Every crossing order
+        //takes part, the clearing volume is what is left on the smaller side.
+        let mut buy_orders = self.auction_orders(OrderSide::Buy, clearing_price.price_);
+        let mut sell_orders = self.auction_orders(OrderSide::Sell, clearing_price.price_);
+        let mut result = MatchingResult::new();
+        let mut buy_index = 0;
+        let mut sell_index = 0;
+        while let (Some(buy), Some(sell)) = (
+            buy_orders.get_mut(buy_index),
+            sell_orders.get_mut(sell_index),
+        ) {
+            if buy.client_id_.is_some() && buy.client_id_ == sell.client_id_ {
+                let report =
+                    Level::prevent_self_match(buy, sell, buy.qty_, self.self_match_policy_);
+                buy.qty_ -= report.aggressor_cancelled_qty_;
+                result.self_match_reports_.push(report);
+            } else {
+                let fill_qty = buy.qty_.min(sell.qty_);
+                buy.qty_ -= fill_qty;
+                sell.qty_ -= fill_qty;
+                result.add_fill(Fill {
+                    trade_id_: 0,
+                    seq_: EventSeq::default(),
+                    aggressor_id_: buy.id_.to_owned(),
+                    resting_id_: sell.id_.to_owned(),
+                    qty_: fill_qty,
+                    price_: clearing_price.price_,
+                    aggressor_leaves_qty_: buy.qty_,
+                    resting_leaves_qty_: sell.qty_,
+                });
+            }
+            if buy.qty_.is_zero() {
+                buy_index += 1;
+            }
+            if sell.qty_.is_zero() {
+                sell_index += 1;
+            }
+        }
+        buy_orders.truncate(buy_index + 1);
+        sell_orders.truncate(sell_index + 1);
+        self.settle_auction_orders(OrderSide::Buy, buy_orders);
+        self.settle_auction_orders(OrderSide::Sell, sell_orders);
+
+        self.last_trade_price_ = Some(clearing_price.price_);
+        let mut match_result = Some(result);
+        self.process_stop_triggers(&mut match_result);
+        Ok(match_result)
+    }
+
+    //Orders of p_side that cross p_clearing_price, best price first and in queue order
+    fn auction_orders(&self, p_side: OrderSide, p_clearing_price: Price) -> Vec<Order> {
+        let levels: Box<dyn Iterator<Item = &Level>> = match p_side {
+            OrderSide::Buy => Box::new(
+                self.bids_
+                    .range(p_clearing_price..)
+                    .rev()
+                    .map(|(_, level)| level),
+            ),
+            OrderSide::Sell => Box::new(
+                self.asks_
+                    .range(..=p_clearing_price)
+                    .map(|(_, level)| level),
+            ),
+        };
+        levels
+            .flat_map(|level| level.orders_.values())
+            .cloned()
+            .collect()
+    }
+
+    //Writes the leaves qty of the orders that took part in the uncross back to the book,
+    //orders executed or cancelled in full leave it
+    fn settle_auction_orders(&mut self, p_side: OrderSide, p_orders: Vec<Order>) {
+        for mut order in p_orders {
+            self.touch_level(p_side, order.price_);
+            if order.qty_.is_zero() {
+                self.remove_order_by_id(&order.id_);
+                continue;
+            }
+            if let Some(display_qty) = order.display_qty_ {
+                order.peak_qty_ = display_qty.min(order.qty_);
+            }
+            if let Some(level) = self.levels_mut(p_side).get_mut(&order.price_) {
+                level.replace_or_remove(order);
+            }
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+//Source of the entry time of orders, in nanoseconds that never go backwards.
+//Owned by the engine, callers never stamp the time themselves.
+pub trait Clock: Debug + Send + Sync {
+    fn now_nanos(&self) -> u64;
+}
+
+//Nanoseconds elapsed since the clock was created, driven by the monotonic clock of the OS
+#[derive(Debug)]
+pub struct MonotonicClock {
+    start_: Instant,
+}
+
+impl MonotonicClock {
+    pub fn new() -> Self {
+        MonotonicClock {
+            start_: Instant::now(),
+        }
+    }
+}
+
+impl Default for MonotonicClock {
+    fn default() -> Self {
+        MonotonicClock::new()
+    }
+}
+
+impl Clock for MonotonicClock {
+    fn now_nanos(&self) -> u64 {
+        self.start_.elapsed().as_nanos() as u64
+    }
+}
+
+//Moves only when it is told to, for tests, simulations and replays.
+//Shared with the engine through an Arc, so the time can be driven while the engine owns it.
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    now_nanos_: AtomicU64,
+}
+
+impl ManualClock {
+    pub fn new(p_now_nanos: u64) -> Self {
+        ManualClock {
+            now_nanos_: AtomicU64::new(p_now_nanos),
+        }
+    }
+
+    //Setting a time in the past is ignored, the clock stays monotonic
+    pub fn set(&self, p_now_nanos: u64) {
+        self.now_nanos_.fetch_max(p_now_nanos, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, p_nanos: u64) {
+        self.now_nanos_.fetch_add(p_nanos, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now_nanos(&self) -> u64 {
+        self.now_nanos_.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,333 @@
+use std::io;
+use std::time::{Duration, SystemTime};
+
+use msg::order::*;
+
+//Little endian binary encoding of the engine messages, shared by the journal and the snapshots.
+//Strings are length prefixed and options carry a presence byte.
+#[derive(Debug, Default)]
+pub(crate) struct Encoder {
+    buf_: Vec<u8>,
+}
+
+impl Encoder {
+    pub(crate) fn new() -> Self {
+        Encoder::default()
+    }
+
+    pub(crate) fn into_bytes(self) -> Vec<u8> {
+        self.buf_
+    }
+
+    pub(crate) fn put_u8(&mut self, p_value: u8) {
+        self.buf_.push(p_value);
+    }
+
+    pub(crate) fn put_u32(&mut self, p_value: u32) {
+        self.buf_.extend_from_slice(&p_value.to_le_bytes());
+    }
+
+    pub(crate) fn put_u64(&mut self, p_value: u64) {
+        self.buf_.extend_from_slice(&p_value.to_le_bytes());
+    }
+
+    pub(crate) fn put_i64(&mut self, p_value: i64) {
+        self.buf_.extend_from_slice(&p_value.to_le_bytes());
+    }
+
+    pub(crate) fn put_bool(&mut self, p_value: bool) {
+        self.put_u8(p_value as u8);
+    }
+
+    pub(crate) fn put_str(&mut self, p_value: &str) {
+        self.put_u32(p_value.len() as u32);
+        self.buf_.extend_from_slice(p_value.as_bytes());
+    }
+
+    pub(crate) fn put_option<T>(&mut self, p_value: Option<T>, p_put: impl FnOnce(&mut Self, T)) {
+        self.put_bool(p_value.is_some());
+        if let Some(value) = p_value {
+            p_put(self, value);
+        }
+    }
+
+    pub(crate) fn put_price(&mut self, p_price: Price) {
+        self.put_i64(p_price.0);
+    }
+
+    pub(crate) fn put_qty(&mut self, p_qty: Quantity) {
+        self.put_u64(p_qty.0);
+    }
+
+    //Nanoseconds since the unix epoch, earlier times are stored as the epoch
+    pub(crate) fn put_time(&mut self, p_time: SystemTime) {
+        let since_epoch = p_time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        self.put_u64(since_epoch.as_nanos() as u64);
+    }
+
+    pub(crate) fn put_side(&mut self, p_side: OrderSide) {
+        self.put_u8(match p_side {
+            OrderSide::Buy => 0,
+            OrderSide::Sell => 1,
+        });
+    }
+
+    pub(crate) fn put_trading_state(&mut self, p_trading_state: TradingState) {
+        self.put_u8(match p_trading_state {
+            TradingState::PreOpen => 0,
+            TradingState::Open => 1,
+            TradingState::Halted => 2,
+            TradingState::Closed => 3,
+        });
+    }
+
+    pub(crate) fn put_order(&mut self, p_order: &Order) {
+        self.put_str(&p_order.id_);
+        self.put_str(&p_order.symbol_);
+        self.put_qty(p_order.qty_);
+        self.put_price(p_order.price_);
+        self.put_u64(p_order.entry_time_);
+        self.put_side(p_order.side_);
+        self.put_u8(match p_order.type_ {
+            OrderType::Mkt => 0,
+            OrderType::Limit => 1,
+            OrderType::Stop => 2,
+            OrderType::StopLimit => 3,
+        });
+        self.put_option(p_order.protection_price_, Encoder::put_price);
+        self.put_u64(p_order.priority_seq_);
+        match p_order.time_in_force_ {
+            TimeInForce::Day => self.put_u8(0),
+            TimeInForce::Gtc => self.put_u8(1),
+            TimeInForce::Ioc => self.put_u8(2),
+            TimeInForce::Fok => self.put_u8(3),
+            TimeInForce::Gtd(expire_time) => {
+                self.put_u8(4);
+                self.put_time(expire_time);
+            }
+        }
+        self.put_price(p_order.stop_price_);
+        self.put_option(p_order.display_qty_, Encoder::put_qty);
+        self.put_qty(p_order.peak_qty_);
+        self.put_option(p_order.post_only_, |encoder, post_only| {
+            encoder.put_u8(match post_only {
+                PostOnly::Reject => 0,
+                PostOnly::Reprice => 1,
+            })
+        });
+        self.put_option(p_order.client_id_.as_deref(), Encoder::put_str);
+    }
+
+    pub(crate) fn put_event(&mut self, p_event: &EventType) {
+        match p_event {
+            EventType::New(order) => {
+                self.put_u8(0);
+                self.put_order(order);
+            }
+            EventType::Rpl(replace_request) => {
+                self.put_u8(1);
+                self.put_str(&replace_request.id_);
+                self.put_str(&replace_request.symbol_);
+                self.put_side(replace_request.side_);
+                self.put_qty(replace_request.qty_);
+                self.put_price(replace_request.price_);
+            }
+            EventType::Cxl(cancel_request) => {
+                self.put_u8(2);
+                self.put_str(&cancel_request.id_);
+            }
+            EventType::Adm(admin_request) => {
+                self.put_u8(3);
+                self.put_str(&admin_request.symbol_);
+                self.put_trading_state(admin_request.trading_state_);
+            }
+        }
+    }
+}
+
+pub(crate) fn invalid_data(p_reason: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, p_reason)
+}
+
+#[derive(Debug)]
+pub(crate) struct Decoder<'a> {
+    buf_: &'a [u8],
+    pos_: usize,
+}
+
+impl<'a> Decoder<'a> {
+    pub(crate) fn new(p_buf: &'a [u8]) -> Self {
+        Decoder {
+            buf_: p_buf,
+            pos_: 0,
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.pos_ == self.buf_.len()
+    }
+
+    fn take(&mut self, p_len: usize) -> io::Result<&'a [u8]> {
+        let end = self.pos_.saturating_add(p_len);
+        let Some(bytes) = self.buf_.get(self.pos_..end) else {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+        };
+        self.pos_ = end;
+        Ok(bytes)
+    }
+
+    fn tag_error(p_kind: &str, p_tag: u8) -> io::Error {
+        invalid_data(format!("Unknown {p_kind} tag {p_tag}"))
+    }
+
+    pub(crate) fn get_u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn get_u32(&mut self) -> io::Result<u32> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub(crate) fn get_u64(&mut self) -> io::Result<u64> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub(crate) fn get_i64(&mut self) -> io::Result<i64> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(i64::from_le_bytes(bytes))
+    }
+
+    pub(crate) fn get_bool(&mut self) -> io::Result<bool> {
+        match self.get_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            tag => Err(Decoder::tag_error("bool", tag)),
+        }
+    }
+
+    pub(crate) fn get_str(&mut self) -> io::Result<String> {
+        let len = self.get_u32()? as usize;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|error| invalid_data(error.to_string()))
+    }
+
+    pub(crate) fn get_option<T>(
+        &mut self,
+        p_get: impl FnOnce(&mut Self) -> io::Result<T>,
+    ) -> io::Result<Option<T>> {
+        if self.get_bool()? {
+            return Ok(Some(p_get(self)?));
+        }
+        Ok(None)
+    }
+
+    pub(crate) fn get_price(&mut self) -> io::Result<Price> {
+        Ok(Price(self.get_i64()?))
+    }
+
+    pub(crate) fn get_qty(&mut self) -> io::Result<Quantity> {
+        Ok(Quantity(self.get_u64()?))
+    }
+
+    pub(crate) fn get_time(&mut self) -> io::Result<SystemTime> {
+        Ok(SystemTime::UNIX_EPOCH + Duration::from_nanos(self.get_u64()?))
+    }
+
+    pub(crate) fn get_side(&mut self) -> io::Result<OrderSide> {
+        match self.get_u8()? {
+            0 => Ok(OrderSide::Buy),
+            1 => Ok(OrderSide::Sell),
+            tag => Err(Decoder::tag_error("side", tag)),
+        }
+    }
+
+    pub(crate) fn get_trading_state(&mut self) -> io::Result<TradingState> {
+        match self.get_u8()? {
+            0 => Ok(TradingState::PreOpen),
+            1 => Ok(TradingState::Open),
+            2 => Ok(TradingState::Halted),
+            3 => Ok(TradingState::Closed),
+            tag => Err(Decoder::tag_error("trading state", tag)),
+        }
+    }
+
+    pub(crate) fn get_order(&mut self) -> io::Result<Order> {
+        let id = self.get_str()?;
+        let symbol = self.get_str()?;
+        let qty = self.get_qty()?;
+        let price = self.get_price()?;
+        let entry_time = self.get_u64()?;
+        let side = self.get_side()?;
+        let order_type = match self.get_u8()? {
+            0 => OrderType::Mkt,
+            1 => OrderType::Limit,
+            2 => OrderType::Stop,
+            3 => OrderType::StopLimit,
+            tag => return Err(Decoder::tag_error("order type", tag)),
+        };
+        let protection_price = self.get_option(Decoder::get_price)?;
+        let priority_seq = self.get_u64()?;
+        let time_in_force = match self.get_u8()? {
+            0 => TimeInForce::Day,
+            1 => TimeInForce::Gtc,
+            2 => TimeInForce::Ioc,
+            3 => TimeInForce::Fok,
+            4 => TimeInForce::Gtd(self.get_time()?),
+            tag => return Err(Decoder::tag_error("time in force", tag)),
+        };
+        let stop_price = self.get_price()?;
+        let display_qty = self.get_option(Decoder::get_qty)?;
+        let peak_qty = self.get_qty()?;
+        let post_only = self.get_option(|decoder| match decoder.get_u8()? {
+            0 => Ok(PostOnly::Reject),
+            1 => Ok(PostOnly::Reprice),
+            tag => Err(Decoder::tag_error("post only", tag)),
+        })?;
+        let client_id = self.get_option(Decoder::get_str)?;
+        Ok(Order {
+            id_: id,
+            symbol_: symbol,
+            qty_: qty,
+            price_: price,
+            entry_time_: entry_time,
+            side_: side,
+            type_: order_type,
+            protection_price_: protection_price,
+            priority_seq_: priority_seq,
+            time_in_force_: time_in_force,
+            stop_price_: stop_price,
+            display_qty_: display_qty,
+            peak_qty_: peak_qty,
+            post_only_: post_only,
+            client_id_: client_id,
+        })
+    }
+
+    pub(crate) fn get_event(&mut self) -> io::Result<EventType> {
+        match self.get_u8()? {
+            0 => Ok(EventType::New(self.get_order()?)),
+            1 => Ok(EventType::Rpl(ReplaceRequest {
+                id_: self.get_str()?,
+                symbol_: self.get_str()?,
+                side_: self.get_side()?,
+                qty_: self.get_qty()?,
+                price_: self.get_price()?,
+            })),
+            2 => Ok(EventType::Cxl(CancelRequest {
+                id_: self.get_str()?,
+            })),
+            3 => Ok(EventType::Adm(AdminRequest {
+                symbol_: self.get_str()?,
+                trading_state_: self.get_trading_state()?,
+            })),
+            tag => Err(Decoder::tag_error("event", tag)),
+        }
+    }
+}
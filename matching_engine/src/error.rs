@@ -0,0 +1,184 @@
+use std::error::Error;
+use std::fmt;
+
+use msg::order::*;
+
+use crate::EventSeq;
+
+//Why the engine rejected a request, every variant maps to one reject code of the gateway
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EngineError {
+    UnknownSymbol {
+        symbol_: String,
+    },
+    UnknownOrder {
+        order_id_: String,
+    },
+    //A live order already has this id, in any order book
+    DuplicateOrderId {
+        order_id_: String,
+    },
+    //Price, stop price or protection price is not a multiple of the tick size
+    InvalidPrice {
+        order_id_: String,
+        price_: Price,
+        tick_size_: Price,
+    },
+    //Qty or display qty is not a multiple of the lot size
+    InvalidLot {
+        order_id_: String,
+        qty_: Quantity,
+        lot_size_: Quantity,
+    },
+    QtyOutOfRange {
+        order_id_: String,
+        qty_: Quantity,
+        min_qty_: Quantity,
+        max_qty_: Quantity,
+    },
+    InvalidDisplayQty {
+        order_id_: String,
+    },
+    PostOnlyNotLimit {
+        order_id_: String,
+    },
+    //Market, IOC and FOK orders can not wait for the uncross
+    NotAcceptedInAuction {
+        order_id_: String,
+    },
+    SymbolChange {
+        order_id_: String,
+    },
+    SideChange {
+        order_id_: String,
+    },
+    BookHalted {
+        symbol_: String,
+    },
+    BookClosed {
+        symbol_: String,
+    },
+    PostOnlyWouldTrade {
+        order_id_: String,
+        price_: Price,
+    },
+    PriceBandBreach {
+        order_id_: String,
+        price_: Price,
+    },
+    NotInAuction {
+        symbol_: String,
+    },
+    InvalidStateTransition {
+        symbol_: String,
+        from_: TradingState,
+        to_: TradingState,
+    },
+    //The event could not be written ahead to the journal, it is not processed
+    JournalWriteFailed {
+        reason_: String,
+    },
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineError::UnknownSymbol { symbol_ } => write!(f, "Unknown symbol {symbol_}"),
+            EngineError::UnknownOrder { order_id_ } => {
+                write!(f, "Order id {order_id_} is not found in any order book")
+            }
+            EngineError::DuplicateOrderId { order_id_ } => {
+                write!(f, "Order id {order_id_} is already used by a live order")
+            }
+            EngineError::InvalidPrice {
+                order_id_,
+                price_,
+                tick_size_,
+            } => write!(
+                f,
+                "Order {order_id_} price {price_:?} is not a multiple of tick size {tick_size_:?}"
+            ),
+            EngineError::InvalidLot {
+                order_id_,
+                qty_,
+                lot_size_,
+            } => write!(
+                f,
+                "Order {order_id_} qty {qty_} is not a multiple of lot size {lot_size_}"
+            ),
+            EngineError::QtyOutOfRange {
+                order_id_,
+                qty_,
+                min_qty_,
+                max_qty_,
+            } => write!(
+                f,
+                "Order {order_id_} qty {qty_} is outside of min qty {min_qty_} and max qty {max_qty_}"
+            ),
+            EngineError::InvalidDisplayQty { order_id_ } => write!(
+                f,
+                "Display qty of iceberg order {order_id_} can not be zero"
+            ),
+            EngineError::PostOnlyNotLimit { order_id_ } => write!(
+                f,
+                "Order {order_id_} is post only, it is valid only for limit orders"
+            ),
+            EngineError::NotAcceptedInAuction { order_id_ } => write!(
+                f,
+                "Order {order_id_} must be able to rest, not accepted during the auction"
+            ),
+            EngineError::SymbolChange { order_id_ } => {
+                write!(f, "Symbol of order {order_id_} can not be changed")
+            }
+            EngineError::SideChange { order_id_ } => {
+                write!(f, "Side of order {order_id_} can not be changed")
+            }
+            EngineError::BookHalted { symbol_ } => {
+                write!(f, "Order book of symbol {symbol_} is halted")
+            }
+            EngineError::BookClosed { symbol_ } => {
+                write!(f, "Order book of symbol {symbol_} is closed")
+            }
+            EngineError::PostOnlyWouldTrade { order_id_, price_ } => write!(
+                f,
+                "Post only order {order_id_} would take liquidity at {price_:?}"
+            ),
+            EngineError::PriceBandBreach { order_id_, price_ } => write!(
+                f,
+                "Order {order_id_} would trade at {price_:?} outside the price band"
+            ),
+            EngineError::NotInAuction { symbol_ } => {
+                write!(f, "Symbol {symbol_} is not in auction")
+            }
+            EngineError::InvalidStateTransition {
+                symbol_,
+                from_,
+                to_,
+            } => write!(f, "Symbol {symbol_} can not move from {from_:?} to {to_:?}"),
+            EngineError::JournalWriteFailed { reason_ } => {
+                write!(f, "Journal write failed, event not processed: {reason_}")
+            }
+        }
+    }
+}
+
+impl Error for EngineError {}
+
+//Reject of an inbound event, sequenced like every other output of the engine
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reject {
+    pub seq_: EventSeq,
+    pub reason_: EngineError,
+}
+
+impl fmt::Display for Reject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}, seq num {}", self.reason_, self.seq_.seq_num_)
+    }
+}
+
+impl Error for Reject {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.reason_)
+    }
+}
@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use msg::order::*;
+
+use crate::allocation::{AllocationAlgorithm, Fifo};
+use crate::error::EngineError;
+
+//What the engine does with an order that would trade outside the price band
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BandBreachAction {
+    Reject,
+    //The book moves to a volatility auction (pre-open) and reopens with an uncross
+    VolatilityHalt,
+}
+
+//Dynamic band around the last trade price, or around the previous close before the first trade
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PriceBand {
+    //Half width of the band in basis points of the reference price
+    pub width_bps_: u32,
+    pub breach_action_: BandBreachAction,
+    pub previous_close_: Option<Price>,
+}
+
+impl PriceBand {
+    //Lowest and highest price an order can trade at
+    pub fn limits(&self, p_reference_price: Price) -> (Price, Price) {
+        let width = (p_reference_price.0 as i128 * self.width_bps_ as i128 / 10_000) as i64;
+        (
+            Price(p_reference_price.0 - width),
+            Price(p_reference_price.0 + width),
+        )
+    }
+}
+
+//Reference data of a tradable instrument
+#[derive(Debug, Clone)]
+pub struct Instrument {
+    pub symbol_: String,
+    pub tick_size_: Price,
+    pub lot_size_: Quantity,
+    pub min_qty_: Quantity,
+    pub max_qty_: Quantity,
+    //Decimal places of the fixed-point prices of this instrument
+    pub price_scale_: u32,
+    pub price_band_: Option<PriceBand>,
+    pub allocation_: Arc<dyn AllocationAlgorithm>,
+}
+
+impl Default for Instrument {
+    fn default() -> Self {
+        Instrument {
+            symbol_: String::new(),
+            tick_size_: Price(1),
+            lot_size_: Quantity(1),
+            min_qty_: Quantity(1),
+            max_qty_: Quantity(u64::MAX),
+            price_scale_: 0,
+            price_band_: None,
+            allocation_: Arc::new(Fifo),
+        }
+    }
+}
+
+impl Instrument {
+    //Fixed-point price of p_value in the scale of this instrument, None if it does not fit
+    pub fn price_from_f64(&self, p_value: f64) -> Option<Price> {
+        Price::from_f64(p_value, self.price_scale_)
+    }
+
+    pub fn price_to_f64(&self, p_price: Price) -> f64 {
+        p_price.to_f64(self.price_scale_)
+    }
+
+    fn is_on_tick(&self, p_price: Price) -> bool {
+        self.tick_size_.0 > 0 && p_price.0 % self.tick_size_.0 == 0
+    }
+
+    fn is_lot_multiple(&self, p_qty: Quantity) -> bool {
+        p_qty.0.is_multiple_of(self.lot_size_.0)
+    }
+
+    //Prices must be on the tick and quantities a multiple of the lot within min/max qty
+    pub fn validate_order(&self, p_order: &Order) -> Result<(), EngineError> {
+        let mut prices = vec![];
+        if matches!(p_order.type_, OrderType::Limit | OrderType::StopLimit) {
+            prices.push(p_order.price_);
+        }
+        if matches!(p_order.type_, OrderType::Stop | OrderType::StopLimit) {
+            prices.push(p_order.stop_price_);
+        }
+        prices.extend(p_order.protection_price_);
+        if let Some(price) = prices.into_iter().find(|price| !self.is_on_tick(*price)) {
+            return Err(EngineError::InvalidPrice {
+                order_id_: p_order.id_.to_owned(),
+                price_: price,
+                tick_size_: self.tick_size_,
+            });
+        }
+
+        let qtys = std::iter::once(p_order.qty_).chain(p_order.display_qty_);
+        if let Some(qty) = qtys.into_iter().find(|qty| !self.is_lot_multiple(*qty)) {
+            return Err(EngineError::InvalidLot {
+                order_id_: p_order.id_.to_owned(),
+                qty_: qty,
+                lot_size_: self.lot_size_,
+            });
+        }
+        if p_order.qty_ < self.min_qty_ || p_order.qty_ > self.max_qty_ {
+            return Err(EngineError::QtyOutOfRange {
+                order_id_: p_order.id_.to_owned(),
+                qty_: p_order.qty_,
+                min_qty_: self.min_qty_,
+                max_qty_: self.max_qty_,
+            });
+        }
+        Ok(())
+    }
+}
+
+//Instruments the engine trades, keyed by symbol
+#[derive(Debug, Clone, Default)]
+pub struct InstrumentRegistry {
+    instruments_: HashMap<String, Instrument>,
+}
+
+impl InstrumentRegistry {
+    pub fn new() -> Self {
+        InstrumentRegistry::default()
+    }
+
+    pub fn add_instrument(&mut self, p_instrument: Instrument) {
+        self.instruments_
+            .insert(p_instrument.symbol_.to_owned(), p_instrument);
+    }
+
+    pub fn get(&self, p_symbol: &String) -> Option<&Instrument> {
+        self.instruments_.get(p_symbol)
+    }
+
+    pub fn instruments(&self) -> impl Iterator<Item = &Instrument> {
+        self.instruments_.values()
+    }
+}
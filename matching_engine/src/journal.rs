@@ -0,0 +1,196 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::SystemTime;
+
+use msg::order::*;
+
+use crate::codec::{invalid_data, Decoder, Encoder};
+
+//When the journal forces its records to the disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FsyncPolicy {
+    //fsync before every record is processed, nothing processed is lost when the machine crashes
+    #[default]
+    EveryRecord,
+    //fsync once every N records, up to N-1 processed records can be lost with the machine
+    EveryN(u32),
+    //left to the OS, records survive a crash of the process only
+    Never,
+}
+
+//Inbound event or engine command that changes the books, written before it is processed.
+//Rejected events are journaled as well, they take sequence numbers too.
+#[derive(Debug, Clone)]
+pub(crate) enum JournalRecord {
+    Event {
+        entry_time_: u64,
+        event_: EventType,
+    },
+    Uncross {
+        symbol_: String,
+        reference_price_: Option<Price>,
+    },
+    ExpireDay,
+    ExpireGtd {
+        now_: SystemTime,
+    },
+}
+
+impl JournalRecord {
+    fn encode(&self, p_encoder: &mut Encoder) {
+        match self {
+            JournalRecord::Event {
+                entry_time_,
+                event_,
+            } => {
+                p_encoder.put_u8(0);
+                p_encoder.put_u64(*entry_time_);
+                p_encoder.put_event(event_);
+            }
+            JournalRecord::Uncross {
+                symbol_,
+                reference_price_,
+            } => {
+                p_encoder.put_u8(1);
+                p_encoder.put_str(symbol_);
+                p_encoder.put_option(*reference_price_, Encoder::put_price);
+            }
+            JournalRecord::ExpireDay => p_encoder.put_u8(2),
+            JournalRecord::ExpireGtd { now_ } => {
+                p_encoder.put_u8(3);
+                p_encoder.put_time(*now_);
+            }
+        }
+    }
+
+    fn decode(p_decoder: &mut Decoder) -> io::Result<JournalRecord> {
+        match p_decoder.get_u8()? {
+            0 => Ok(JournalRecord::Event {
+                entry_time_: p_decoder.get_u64()?,
+                event_: p_decoder.get_event()?,
+            }),
+            1 => Ok(JournalRecord::Uncross {
+                symbol_: p_decoder.get_str()?,
+                reference_price_: p_decoder.get_option(Decoder::get_price)?,
+            }),
+            2 => Ok(JournalRecord::ExpireDay),
+            3 => Ok(JournalRecord::ExpireGtd {
+                now_: p_decoder.get_time()?,
+            }),
+            tag => Err(invalid_data(format!("Unknown journal record tag {tag}"))),
+        }
+    }
+}
+
+//Append-only file of records, each one is its length as u32 followed by the encoded record.
+//A record is handed to the OS in a single write before the engine processes it.
+#[derive(Debug)]
+pub struct Journal {
+    file_: File,
+    fsync_policy_: FsyncPolicy,
+    unsynced_records_: u32,
+    //Bytes of the next record written before the write fails, simulates a full disk
+    #[cfg(test)]
+    pub(crate) fail_after_: Option<usize>,
+}
+
+impl Journal {
+    pub fn open(p_path: &Path, p_fsync_policy: FsyncPolicy) -> io::Result<Journal> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(p_path)?;
+        Ok(Journal {
+            file_: file,
+            fsync_policy_: p_fsync_policy,
+            unsynced_records_: 0,
+            #[cfg(test)]
+            fail_after_: None,
+        })
+    }
+
+    //Every complete record from the start of the file. A record torn by a crash can only be
+    //the last one, it was never processed and is cut off so that appends follow the last record.
+    pub(crate) fn read_records(&mut self) -> io::Result<Vec<JournalRecord>> {
+        let mut bytes = Vec::new();
+        self.file_.seek(SeekFrom::Start(0))?;
+        self.file_.read_to_end(&mut bytes)?;
+
+        let mut records = Vec::new();
+        let mut valid_len = 0;
+        while let Some(len_bytes) = bytes.get(valid_len..valid_len + 4) {
+            let record_start = valid_len + 4;
+            let record_len =
+                u32::from_le_bytes([len_bytes[0], len_bytes[1], len_bytes[2], len_bytes[3]]);
+            let Some(record_bytes) = bytes.get(record_start..record_start + record_len as usize)
+            else {
+                break;
+            };
+            let mut decoder = Decoder::new(record_bytes);
+            records.push(JournalRecord::decode(&mut decoder)?);
+            if !decoder.is_empty() {
+                return Err(invalid_data(format!(
+                    "Journal record at offset {valid_len} is longer than its content"
+                )));
+            }
+            valid_len = record_start + record_len as usize;
+        }
+
+        if valid_len < bytes.len() {
+            println!(
+                "Journal has a torn record at offset {valid_len}, {} bytes are dropped",
+                bytes.len() - valid_len
+            );
+            self.file_.set_len(valid_len as u64)?;
+        }
+        self.file_.seek(SeekFrom::Start(valid_len as u64))?;
+        Ok(records)
+    }
+
+    pub(crate) fn append(&mut self, p_record: &JournalRecord) -> io::Result<()> {
+        let mut encoder = Encoder::new();
+        p_record.encode(&mut encoder);
+        let record = encoder.into_bytes();
+
+        let mut frame = Vec::with_capacity(4 + record.len());
+        frame.extend_from_slice(&(record.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&record);
+        //a partial frame would be read back as the start of the next record, it is cut off
+        let record_start = self.file_.stream_position()?;
+        if let Err(error) = self.write_frame(&frame) {
+            self.file_.set_len(record_start)?;
+            self.file_.seek(SeekFrom::Start(record_start))?;
+            return Err(error);
+        }
+
+        self.unsynced_records_ += 1;
+        let sync = match self.fsync_policy_ {
+            FsyncPolicy::EveryRecord => true,
+            FsyncPolicy::EveryN(records) => self.unsynced_records_ >= records,
+            FsyncPolicy::Never => false,
+        };
+        if sync {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    fn write_frame(&mut self, p_frame: &[u8]) -> io::Result<()> {
+        #[cfg(test)]
+        if let Some(fail_after) = self.fail_after_.take() {
+            self.file_
+                .write_all(&p_frame[..fail_after.min(p_frame.len())])?;
+            return Err(io::Error::other("short write"));
+        }
+        self.file_.write_all(p_frame)
+    }
+
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.file_.sync_data()?;
+        self.unsynced_records_ = 0;
+        Ok(())
+    }
+}
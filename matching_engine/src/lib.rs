@@ -1,23 +1,241 @@
 use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::collections::BTreeSet;
 use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::io;
+use std::path::Path;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::time::SystemTime;
 
 use msg::order::*;
 
+pub mod allocation;
+pub mod auction;
+pub mod clock;
+mod codec;
+pub mod error;
+pub mod instrument;
+pub mod journal;
+pub mod market_data;
+pub mod snapshot;
+mod stop_book;
+use allocation::AllocationAlgorithm;
+use auction::ClearingPrice;
+use clock::{Clock, MonotonicClock};
+use error::{EngineError, Reject};
+use instrument::{BandBreachAction, Instrument, InstrumentRegistry, PriceBand};
+use journal::{FsyncPolicy, Journal, JournalRecord};
+use market_data::{BboUpdate, DepthSubscriber};
+use stop_book::StopBook;
+
+//Position of an output in the engine-wide stream and in the stream of its symbol, both start
+//at 1 and have no gaps. Journal, market data and drop copy refer to events by these numbers.
+//symbol_seq_num_ is 0 on a reject of an unknown symbol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EventSeq {
+    pub seq_num_: u64,
+    pub symbol_seq_num_: u64,
+}
+
+//One execution between an incoming (aggressor) order and a resting order,
+//both counterparties book their execution from the same fill.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fill {
+    pub trade_id_: u64,
+    pub seq_: EventSeq,
+    pub aggressor_id_: String,
+    pub resting_id_: String,
+    pub qty_: Quantity,
+    pub price_: Price,
+    pub aggressor_leaves_qty_: Quantity,
+    pub resting_leaves_qty_: Quantity,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CancelReason {
+    DayExpired,
+    GtdExpired,
+}
+
+//Unsolicited cancel of a resting order, generated by the engine
+#[derive(Debug, Clone, PartialEq)]
+pub struct CancelReport {
+    pub seq_: EventSeq,
+    pub order_id_: String,
+    pub symbol_: String,
+    pub cancelled_qty_: Quantity,
+    pub reason_: CancelReason,
+}
+
+//What the engine does when an aggressor meets a resting order of the same client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SelfMatchPolicy {
+    #[default]
+    CancelResting,
+    CancelAggressor,
+    CancelBoth,
+    //both orders are reduced by the smaller of their quantities without a trade
+    DecrementBoth,
+}
+
+//Self-match prevented by the engine, quantities are what was cancelled on each side
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelfMatchReport {
+    pub seq_: EventSeq,
+    pub aggressor_id_: String,
+    pub resting_id_: String,
+    pub policy_: SelfMatchPolicy,
+    pub aggressor_cancelled_qty_: Quantity,
+    pub resting_cancelled_qty_: Quantity,
+    pub resting_leaves_qty_: Quantity,
+}
+
+//Published on every trading state transition of a symbol
+#[derive(Debug, Clone, PartialEq)]
+pub struct TradingStateChange {
+    pub seq_: EventSeq,
+    pub symbol_: String,
+    pub previous_state_: TradingState,
+    pub trading_state_: TradingState,
+}
+
+//Aggregated price level as seen by the market, hidden reserve of iceberg orders is not included
+#[derive(Debug, Clone, PartialEq)]
+pub struct DepthLevel {
+    pub price_: Price,
+    pub qty_: Quantity,
+    pub order_count_: usize,
+}
+
+//Stop order that was triggered while processing an event, with the result of its matching.
+//A stop that starts a volatility auction goes back to the trigger book with its leaves qty,
+//a rejected stop has no leaves qty and its reject reason.
+#[derive(Debug, Clone)]
+pub struct TriggeredOrder {
+    pub seq_: EventSeq,
+    pub order_id_: String,
+    pub leaves_qty_: Quantity,
+    pub match_result_: Option<MatchingResult>,
+    pub reject_reason_: Option<EngineError>,
+}
+
 #[derive(Debug, Clone)]
 pub struct MatchingResult {
-    matched_order_ids_: Vec<String>,
-    executed_qty_: i32,
-    executed_price_: f32,
+    //ack of the inbound event, the outputs it caused are sequenced after it
+    seq_: EventSeq,
+    fills_: Vec<Fill>,
+    executed_qty_: Quantity,
+    cancelled_qty_: Quantity,
+    //in the order they were injected into the book
+    triggered_orders_: Vec<TriggeredOrder>,
+    //new price of a post-only order that was re-priced behind the touch
+    repriced_price_: Option<Price>,
+    self_match_reports_: Vec<SelfMatchReport>,
+    trading_state_change_: Option<TradingStateChange>,
 }
 
 impl MatchingResult {
     fn new() -> Self {
         MatchingResult {
-            matched_order_ids_: Vec::new(),
-            executed_qty_: 0,
-            executed_price_: 0.0,
+            seq_: EventSeq::default(),
+            fills_: Vec::new(),
+            executed_qty_: Quantity::ZERO,
+            cancelled_qty_: Quantity::ZERO,
+            triggered_orders_: Vec::new(),
+            repriced_price_: None,
+            self_match_reports_: Vec::new(),
+            trading_state_change_: None,
+        }
+    }
+
+    fn add_fill(&mut self, p_fill: Fill) {
+        self.executed_qty_ += p_fill.qty_;
+        self.fills_.push(p_fill);
+    }
+
+    fn merge(&mut self, p_other: MatchingResult) {
+        self.executed_qty_ += p_other.executed_qty_;
+        self.fills_.extend(p_other.fills_);
+        self.self_match_reports_.extend(p_other.self_match_reports_);
+    }
+
+    //State changes of the match and of its triggered orders, in the order they happened
+    fn trading_state_changes(&self) -> Vec<TradingStateChange> {
+        let mut trading_state_changes: Vec<TradingStateChange> =
+            self.trading_state_change_.iter().cloned().collect();
+        for triggered_order in &self.triggered_orders_ {
+            if let Some(match_result) = &triggered_order.match_result_ {
+                trading_state_changes.extend(match_result.trading_state_changes());
+            }
         }
+        trading_state_changes
+    }
+
+    //aggressor qty cancelled by self-match prevention, it is neither executed nor resting
+    fn self_match_cancelled_qty(&self) -> Quantity {
+        let mut cancelled_qty = Quantity::ZERO;
+        for report in &self.self_match_reports_ {
+            cancelled_qty += report.aggressor_cancelled_qty_;
+        }
+        cancelled_qty
+    }
+
+    //Lowest and highest price of p_printed_prices and of the fills of this match
+    fn widen_printed_prices(
+        &self,
+        p_printed_prices: Option<(Price, Price)>,
+    ) -> Option<(Price, Price)> {
+        self.fills_.iter().fold(
+            p_printed_prices,
+            |printed_prices, fill| match printed_prices {
+                None => Some((fill.price_, fill.price_)),
+                Some((lowest_price, highest_price)) => Some((
+                    lowest_price.min(fill.price_),
+                    highest_price.max(fill.price_),
+                )),
+            },
+        )
+    }
+
+    pub fn seq(&self) -> EventSeq {
+        self.seq_
+    }
+
+    pub fn fills(&self) -> &Vec<Fill> {
+        &self.fills_
+    }
+
+    pub fn executed_qty(&self) -> Quantity {
+        self.executed_qty_
+    }
+
+    pub fn cancelled_qty(&self) -> Quantity {
+        self.cancelled_qty_
+    }
+
+    pub fn triggered_orders(&self) -> &Vec<TriggeredOrder> {
+        &self.triggered_orders_
+    }
+
+    pub fn repriced_price(&self) -> Option<Price> {
+        self.repriced_price_
+    }
+
+    pub fn self_match_reports(&self) -> &Vec<SelfMatchReport> {
+        &self.self_match_reports_
+    }
+
+    pub fn trading_state_change(&self) -> Option<&TradingStateChange> {
+        self.trading_state_change_.as_ref()
+    }
+
+    pub fn executed_notional(&self) -> i128 {
+        self.fills_
+            .iter()
+            .map(|fill| fill.price_.notional(fill.qty_))
+            .sum()
     }
 }
 
@@ -35,9 +253,7 @@ impl PartialOrd for MatchingResult {
 
 impl Ord for MatchingResult {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.executed_qty_
-            .partial_cmp(&other.executed_qty_)
-            .unwrap_or(Ordering::Equal)
+        self.executed_qty_.cmp(&other.executed_qty_)
     }
 }
 
@@ -46,472 +262,1544 @@ impl Eq for MatchingResult {}
 
 #[derive(Clone, Debug)]
 struct Level {
-    orders_: BTreeSet<Order>,
-    price_: f32,
-    side_: OrderSide,
-}
-
-impl PartialOrd for Level {
-    fn partial_cmp(&self, p_other: &Self) -> Option<Ordering> {
-        Some(self.cmp(p_other))
-    }
+    //Orders in the level queue keyed by their priority_seq_, changed through the Level methods
+    //only so that displayed_qty_ stays in line
+    orders_: BTreeMap<u64, Order>,
+    price_: Price,
+    //Sum of the displayed qty of the orders, kept up to date for market data
+    displayed_qty_: Quantity,
 }
 
-impl PartialEq for Level {
-    fn eq(&self, p_other: &Self) -> bool {
-        self.cmp(p_other) == Ordering::Equal
+impl Level {
+    fn from_order(p_order: &Order) -> Self {
+        Level {
+            price_: p_order.price_,
+            orders_: BTreeMap::new(),
+            displayed_qty_: Quantity::ZERO,
+        }
     }
-}
 
-impl Ord for Level {
-    fn cmp(&self, p_other: &Self) -> Ordering {
-        self.compare(p_other)
+    fn add_order(&mut self, p_order: &Order) {
+        self.displayed_qty_ += p_order.displayed_qty();
+        self.orders_
+            .insert(p_order.priority_seq_, p_order.to_owned());
+        println!(
+            "Order id {:?} added into level {:?}",
+            p_order.id_, self.price_
+        );
     }
-}
 
-impl Eq for Level {}
-
-impl Level {
-    fn compare(&self, p_other: &Self) -> Ordering {
-        //assert!(self.side_ == p_other.side_);
-        match self.side_ {
-            OrderSide::Buy => p_other
-                .price_
-                .partial_cmp(&self.price_)
-                .unwrap_or(Ordering::Equal),
-            OrderSide::Sell => self
-                .price_
-                .partial_cmp(&p_other.price_)
-                .unwrap_or(Ordering::Equal),
+    //Includes the hidden reserve of iceberg orders, it can be matched after replenishment
+    fn total_qty(&self) -> Quantity {
+        let mut total_qty = Quantity::ZERO;
+        for order in self.orders_.values() {
+            total_qty += order.qty_;
         }
+        total_qty
     }
 
-    fn from_order(p_order: &Order) -> Self {
-        let new_level = Level {
-            price_: p_order.price_,
-            orders_: BTreeSet::new(),
-            side_: p_order.side_,
-        };
-        new_level
+    fn displayed_qty(&self) -> Quantity {
+        self.displayed_qty_
     }
 
-    fn from_first_order(p_order: &Order) -> Self {
-        let mut new_level = Level::from_order(p_order);
-        new_level.add_order(p_order);
-        new_level
+    fn remove_order(&mut self, p_priority_seq: u64) -> Option<Order> {
+        let order = self.orders_.remove(&p_priority_seq)?;
+        self.displayed_qty_ -= order.displayed_qty();
+        Some(order)
     }
 
-    fn add_order(&mut self, p_order: &Order) {
-        self.orders_.insert(p_order.to_owned());
-        println!("Order id {:?} added into {:?}", p_order.id_, self);
+    //Resting qty of the aggressor's own client, it is never filled against the aggressor
+    fn own_client_qty(&self, p_aggressor: &Order) -> Quantity {
+        let mut own_client_qty = Quantity::ZERO;
+        if p_aggressor.client_id_.is_none() {
+            return own_client_qty;
+        }
+        for order in self.orders_.values() {
+            if order.client_id_ == p_aggressor.client_id_ {
+                own_client_qty += order.qty_;
+            }
+        }
+        own_client_qty
     }
 
-    fn remove_order(&mut self, p_remove_order: &Order) -> bool {
-        self.orders_.remove(p_remove_order)
+    fn crosses(&self, p_order: &Order) -> bool {
+        let limit_price = match p_order.type_ {
+            OrderType::Mkt | OrderType::Stop => match p_order.protection_price_ {
+                None => return true,
+                Some(protection_price) => protection_price,
+            },
+            OrderType::Limit | OrderType::StopLimit => p_order.price_,
+        };
+        match p_order.side_ {
+            OrderSide::Buy => self.price_ <= limit_price,
+            OrderSide::Sell => self.price_ >= limit_price,
+        }
     }
 
-    fn match_order(&mut self, p_order: &mut Order) -> Result<Option<MatchingResult>, String> {
+    fn match_order(
+        &mut self,
+        p_aggressor: &Order,
+        p_qty: Quantity,
+        p_self_match_policy: SelfMatchPolicy,
+        p_allocation: &dyn AllocationAlgorithm,
+    ) -> Result<Option<MatchingResult>, EngineError> {
         //match the qty
-        //step 1: get copy of first order
-        //step 2: if p_order qty is == first order then remove that order and return total qty of current order as Ok
-        //step 2: if p_order qty is < first order then replace that order with qty = that order qty - p_order qty
-        //step 3: of p_order qty is > first order then remove first order, p_order.qty -= first_order.qty_  and repeat from step 1
+        //step 1: split the remaining qty between the orders of the level with the allocation algorithm
+        //step 2: fill every allocation at the level price in the order given by the algorithm
+        //step 3: if an order is fully filled remove it, otherwise replace it with its leaves qty
+        //step 4: an order of the aggressor's client is never filled, self-match prevention is
+        //applied to it and the remaining qty is allocated again from step 1
+        //An iceberg order only trades its peak, a consumed peak is left empty
+        //so that the book can replenish it at the back of the queue.
 
-        let mut executed_qty = 0;
-        let mut remaining_qty = p_order.qty_;
-        let mut avg_matched_price = 0.0;
+        let mut remaining_qty = p_qty;
 
         println!("Executing {remaining_qty}");
         let mut result = MatchingResult::new();
-        while remaining_qty > 0 && !self.orders_.is_empty() {
-            let first_order_if_any = self.orders_.first();
-            match first_order_if_any {
-                None => {
-                    return Ok(None);
+        while !remaining_qty.is_zero() {
+            let allocations = p_allocation.allocate(&self.orders_, remaining_qty);
+            if allocations.is_empty() {
+                break;
+            }
+
+            let mut self_matched = false;
+            for (priority_seq, allocated_qty) in allocations {
+                let Some(resting_order) = self.orders_.get(&priority_seq) else {
+                    continue;
+                };
+                let mut copy_of_resting_order = resting_order.clone();
+                println!("match found order:\n\t {:?}", copy_of_resting_order);
+
+                if p_aggressor.client_id_.is_some()
+                    && p_aggressor.client_id_ == copy_of_resting_order.client_id_
+                {
+                    let report = Level::prevent_self_match(
+                        p_aggressor,
+                        &mut copy_of_resting_order,
+                        remaining_qty,
+                        p_self_match_policy,
+                    );
+                    remaining_qty -= report.aggressor_cancelled_qty_;
+                    result.self_match_reports_.push(report);
+                    self.replace_or_remove(copy_of_resting_order);
+                    self_matched = true;
+                    break;
                 }
 
-                Some(first_order) => {
-                    result.matched_order_ids_.push(first_order.id_.to_owned());
-                    let mut copy_of_first_order = (*first_order).clone();
-                    println!("match found order:\n\t {:?}", copy_of_first_order);
-
-                    if remaining_qty == copy_of_first_order.qty_ {
-                        //remove order and return exec qty
-                        copy_of_first_order.qty_ = 0;
-                        executed_qty = remaining_qty;
-                        remaining_qty = 0;
-                        avg_matched_price += copy_of_first_order.price_ * executed_qty as f32;
-                        self.orders_.pop_first();
-                    } else if remaining_qty < copy_of_first_order.qty_ {
-                        executed_qty += remaining_qty;
-                        copy_of_first_order.qty_ -= remaining_qty;
-                        avg_matched_price += copy_of_first_order.price_ * remaining_qty as f32;
-                        remaining_qty = 0;
-                        println!("{executed_qty}  is executed and {remaining_qty} remaining, inplace order\n\t {:?}", copy_of_first_order);
-                        self.orders_.replace(copy_of_first_order);
-                        println!("Orders in level after this match:\n\t {:?}", self.orders_);
-                    } else if remaining_qty > copy_of_first_order.qty_ {
-                        let being_executed = copy_of_first_order.qty_;
-                        copy_of_first_order.qty_ -= 0;
-                        executed_qty += being_executed;
-                        remaining_qty -= being_executed;
-                        println!(
-                            "{being_executed}  is being executed and {remaining_qty} remaining."
-                        );
-                        avg_matched_price += copy_of_first_order.price_ * being_executed as f32;
-                        self.orders_.pop_first();
-                    }
+                let fill_qty = remaining_qty.min(allocated_qty);
+                remaining_qty -= fill_qty;
+                copy_of_resting_order.qty_ -= fill_qty;
+                if copy_of_resting_order.display_qty_.is_some() {
+                    copy_of_resting_order.peak_qty_ -= fill_qty;
                 }
+                result.add_fill(Fill {
+                    trade_id_: 0,
+                    seq_: EventSeq::default(),
+                    aggressor_id_: p_aggressor.id_.to_owned(),
+                    resting_id_: copy_of_resting_order.id_.to_owned(),
+                    qty_: fill_qty,
+                    price_: self.price_,
+                    aggressor_leaves_qty_: remaining_qty,
+                    resting_leaves_qty_: copy_of_resting_order.qty_,
+                });
+                println!("{fill_qty}  is executed and {remaining_qty} remaining.");
+                self.replace_or_remove(copy_of_resting_order);
+            }
+
+            //allocation covers the whole displayed qty, nothing more to match here till the book
+            //replenishes the consumed peaks
+            if !self_matched {
+                break;
             }
         }
-        result.executed_qty_ = executed_qty;
-        if executed_qty > 0 {
-            result.executed_price_ = avg_matched_price / executed_qty as f32;
+        println!("Orders in level after this match:\n\t {:?}", self.orders_);
+        Ok(Some(result))
+    }
+
+    fn replace_or_remove(&mut self, p_order: Order) {
+        self.remove_order(p_order.priority_seq_);
+        if !p_order.qty_.is_zero() {
+            self.add_order(&p_order);
+        }
+    }
+
+    //Resting order keeps its priority when it is only decremented
+    fn prevent_self_match(
+        p_aggressor: &Order,
+        p_resting_order: &mut Order,
+        p_remaining_qty: Quantity,
+        p_policy: SelfMatchPolicy,
+    ) -> SelfMatchReport {
+        let (aggressor_cancelled_qty, resting_cancelled_qty) = match p_policy {
+            SelfMatchPolicy::CancelResting => (Quantity::ZERO, p_resting_order.qty_),
+            SelfMatchPolicy::CancelAggressor => (p_remaining_qty, Quantity::ZERO),
+            SelfMatchPolicy::CancelBoth => (p_remaining_qty, p_resting_order.qty_),
+            SelfMatchPolicy::DecrementBoth => {
+                let decrement_qty = p_remaining_qty.min(p_resting_order.qty_);
+                (decrement_qty, decrement_qty)
+            }
+        };
+        p_resting_order.qty_ -= resting_cancelled_qty;
+        p_resting_order.peak_qty_ = p_resting_order.peak_qty_.min(p_resting_order.qty_);
+        println!(
+            "Self match of order id {:?} with order id {:?}, {:?} applied",
+            p_aggressor.id_, p_resting_order.id_, p_policy
+        );
+        SelfMatchReport {
+            seq_: EventSeq::default(),
+            aggressor_id_: p_aggressor.id_.to_owned(),
+            resting_id_: p_resting_order.id_.to_owned(),
+            policy_: p_policy,
+            aggressor_cancelled_qty_: aggressor_cancelled_qty,
+            resting_cancelled_qty_: resting_cancelled_qty,
+            resting_leaves_qty_: p_resting_order.qty_,
         }
-        return Ok(Some(result));
     }
 }
 
+//Where a resting order sits in the book, the queue handle is its priority_seq_
+#[derive(Clone, Copy, Debug)]
+struct OrderLocation {
+    side_: OrderSide,
+    price_: Price,
+    priority_seq_: u64,
+}
+
 #[derive(Debug)]
 struct OrderBook {
-    bids_: BTreeSet<Level>,
-    asks_: BTreeSet<Level>,
+    //Levels keyed by price, best bid is the last entry and best ask is the first entry
+    bids_: BTreeMap<Price, Level>,
+    asks_: BTreeMap<Price, Level>,
+    order_index_: HashMap<String, OrderLocation>,
+    last_priority_seq_: u64,
+    stop_book_: StopBook,
+    last_trade_price_: Option<Price>,
+    //Smallest price increment of the instrument
+    tick_size_: Price,
+    self_match_policy_: SelfMatchPolicy,
+    //Splits the qty between the orders of a level, chosen per instrument
+    allocation_: Arc<dyn AllocationAlgorithm>,
+    //Pre-open is the call phase, orders accumulate without trading till the uncross
+    trading_state_: TradingState,
+    price_band_: Option<PriceBand>,
+    //Sequence number of the last output of this symbol
+    last_seq_num_: u64,
+    //Best bid and offer as last published to the market
+    published_bbo_: (Option<DepthLevel>, Option<DepthLevel>),
+    //Prices of the levels changed since the last depth publication
+    touched_bids_: BTreeSet<Price>,
+    touched_asks_: BTreeSet<Price>,
 }
 
 impl OrderBook {
-    fn add_first_order(&mut self, p_order: &mut Order) -> Result<Option<MatchingResult>, String> {
-        match p_order.side_ {
-            OrderSide::Buy => {
-                self.bids_.insert(Level::from_first_order(p_order));
-                Ok(None)
-            }
+    fn levels_mut(&mut self, p_side: OrderSide) -> &mut BTreeMap<Price, Level> {
+        match p_side {
+            OrderSide::Buy => &mut self.bids_,
+            OrderSide::Sell => &mut self.asks_,
+        }
+    }
 
-            OrderSide::Sell => {
-                self.asks_.insert(Level::from_first_order(p_order));
-                Ok(None)
-            }
+    //Marks the level at p_price for the next depth publication
+    fn touch_level(&mut self, p_side: OrderSide, p_price: Price) {
+        match p_side {
+            OrderSide::Buy => self.touched_bids_.insert(p_price),
+            OrderSide::Sell => self.touched_asks_.insert(p_price),
+        };
+    }
+
+    fn best_level(&self, p_side: OrderSide) -> Option<&Level> {
+        match p_side {
+            OrderSide::Buy => self.bids_.values().next_back(),
+            OrderSide::Sell => self.asks_.values().next(),
         }
     }
 
-    fn get_level_match(&self, p_input_order: &Order) -> Option<&Level> {
-        match p_input_order.side_ {
-            OrderSide::Buy => match p_input_order.type_ {
-                OrderType::Mkt => {
-                    self.asks_.first()
-                }
-                OrderType::Limit => {
-                    self.asks_.get(&Level::from_order(p_input_order))
-                }
-            },
-            OrderSide::Sell => match p_input_order.type_ {
-                OrderType::Mkt => {
-                    self.bids_.first()
-                }
-                OrderType::Limit => {
-                    self.bids_.get(&Level::from_order(p_input_order))
-                }
-            },
+    fn get_level_match(&mut self, p_input_order: &Order) -> Option<&mut Level> {
+        let contra_side = p_input_order.side_.opposite();
+        let level_price = self
+            .best_level(contra_side)
+            .filter(|level| level.crosses(p_input_order))?
+            .price_;
+        self.levels_mut(contra_side).get_mut(&level_price)
+    }
+
+    //Levels an order of p_side trades against, best price first
+    fn contra_levels(&self, p_side: OrderSide) -> Box<dyn Iterator<Item = &Level> + '_> {
+        match p_side {
+            OrderSide::Buy => Box::new(self.asks_.values()),
+            OrderSide::Sell => Box::new(self.bids_.values().rev()),
         }
     }
 
-    fn get_level_match_from_id(&self, p_order: &Order) -> Option<(&Level, &Order)> {
-        match p_order.side_ {
-            OrderSide::Buy => {
-                for level in &self.bids_ {
-                    for order in &level.orders_ {
-                        if order.id_ == p_order.id_ {
-                            return Some((level, order));
-                        }
-                    }
-                }
+    //Whether the opposite side fills the whole order. Self-match prevention would cancel part of
+    //it, so a level with a resting order of its own client is never counted on.
+    fn fills_in_full(&self, p_order: &Order) -> bool {
+        let mut available_qty = Quantity::ZERO;
+        let contra_levels = self.contra_levels(p_order.side_);
+        for level in contra_levels.take_while(|level| level.crosses(p_order)) {
+            if !level.own_client_qty(p_order).is_zero() {
+                return false;
             }
-
-            OrderSide::Sell => {
-                for level in &self.asks_ {
-                    for order in &level.orders_ {
-                        if order.id_ == p_order.id_ {
-                            return Some((level, order));
-                        }
-                    }
-                }
+            available_qty += level.total_qty();
+            if available_qty >= p_order.qty_ {
+                return true;
             }
         }
-        None
+        false
     }
 
-    fn match_order(&mut self, p_order: &mut Order) -> Result<Option<MatchingResult>, String> {
-        let found_level = self.get_level_match(p_order);
-        match found_level {
-            None => {
-                Ok(None)
-            }
-            Some(matched_level) => {
-                println!("Matched to {:?}", matched_level);
-                let mut copy_of_matched_level = (*matched_level).clone();
-                let match_result = copy_of_matched_level.match_order(p_order)?;
+    fn takes_liquidity(&self, p_order: &Order) -> bool {
+        self.best_level(p_order.side_.opposite())
+            .is_some_and(|level| level.crosses(p_order))
+    }
 
-                if match_result.is_none() {
-                    return Ok(None);
-                }
+    //Post-only order never trades on entry, it is either rejected
+    //or moved one tick behind the opposite best
+    fn apply_post_only(&self, p_order: &mut Order) -> Result<Option<Price>, EngineError> {
+        let Some(post_only) = p_order.post_only_ else {
+            return Ok(None);
+        };
+        let Some(contra_level) = self
+            .best_level(p_order.side_.opposite())
+            .filter(|level| level.crosses(p_order))
+        else {
+            return Ok(None);
+        };
 
-                match p_order.side_ {
-                    OrderSide::Buy => {
-                        if copy_of_matched_level.orders_.is_empty() {
-                            self.asks_.remove(&copy_of_matched_level);
-                        } else {
-                            self.asks_.replace(copy_of_matched_level);
-                        }
-                    }
-                    OrderSide::Sell => {
-                        if copy_of_matched_level.orders_.is_empty() {
-                            self.bids_.remove(&copy_of_matched_level);
-                        } else {
-                            self.bids_.replace(copy_of_matched_level);
-                        }
-                    }
-                }
-                println!("After match {:?}", self);
-                Ok(match_result)
+        match post_only {
+            PostOnly::Reject => Err(EngineError::PostOnlyWouldTrade {
+                order_id_: p_order.id_.to_owned(),
+                price_: contra_level.price_,
+            }),
+            PostOnly::Reprice => {
+                p_order.price_ = match p_order.side_ {
+                    OrderSide::Buy => Price(contra_level.price_.0 - self.tick_size_.0),
+                    OrderSide::Sell => Price(contra_level.price_.0 + self.tick_size_.0),
+                };
+                println!(
+                    "Post only order id {:?} re-priced to {:?}",
+                    p_order.id_, p_order.price_
+                );
+                Ok(Some(p_order.price_))
             }
         }
     }
 
-    fn add_order(&mut self, p_order: &mut Order) {
-        let mut temp_level = Level::from_order(&p_order);
-        match p_order.side_ {
-            OrderSide::Buy => {
-                let found_level = self.bids_.get(&temp_level);
+    //Price of the first level the order would trade at outside the price band
+    fn band_breach(&self, p_order: &Order) -> Option<Price> {
+        let price_band = self.price_band_?;
+        let reference_price = self.last_trade_price_.or(price_band.previous_close_)?;
+        let (lowest_price, highest_price) = price_band.limits(reference_price);
 
-                match found_level {
-                    None => {
-                        temp_level.add_order(p_order);
-                        self.bids_.insert(temp_level);
-                    }
+        let mut available_qty = Quantity::ZERO;
+        let contra_levels = self.contra_levels(p_order.side_);
+        for level in contra_levels.take_while(|level| level.crosses(p_order)) {
+            if level.price_ < lowest_price || level.price_ > highest_price {
+                return Some(level.price_);
+            }
+            //qty of its own client is cancelled by self-match prevention, the order goes deeper
+            available_qty += level.total_qty() - level.own_client_qty(p_order);
+            if available_qty >= p_order.qty_ {
+                break;
+            }
+        }
+        None
+    }
 
-                    Some(current_level) => {
-                        let mut copy_of_found_level = (*current_level).clone();
-                        copy_of_found_level.add_order(p_order);
-                        self.bids_.replace(copy_of_found_level);
-                    }
-                }
+    //Nothing is filled when the band is breached, the order is rejected or the book moves to a
+    //volatility auction. A limit order then rests for the uncross, the qty of a market order is
+    //cancelled and a triggered stop goes back to the trigger book.
+    fn apply_band_breach(
+        &mut self,
+        p_order: &Order,
+        p_breach_price: Price,
+    ) -> Result<Option<MatchingResult>, EngineError> {
+        let breach_action = self
+            .price_band_
+            .map_or(BandBreachAction::Reject, |price_band| {
+                price_band.breach_action_
+            });
+        match breach_action {
+            BandBreachAction::Reject => Err(EngineError::PriceBandBreach {
+                order_id_: p_order.id_.to_owned(),
+                price_: p_breach_price,
+            }),
+            BandBreachAction::VolatilityHalt => {
+                println!(
+                    "Order id {:?} would trade at {:?} outside the price band, volatility auction started",
+                    p_order.id_, p_breach_price
+                );
+                let mut result = MatchingResult::new();
+                result.trading_state_change_ = Some(TradingStateChange {
+                    seq_: EventSeq::default(),
+                    symbol_: p_order.symbol_.to_owned(),
+                    previous_state_: self.trading_state_,
+                    trading_state_: TradingState::PreOpen,
+                });
+                self.trading_state_ = TradingState::PreOpen;
+                Ok(Some(result))
             }
+        }
+    }
 
-            OrderSide::Sell => {
-                let found_level = self.asks_.get(&temp_level);
+    fn match_order(&mut self, p_order: &mut Order) -> Result<Option<MatchingResult>, EngineError> {
+        if let Some(repriced_price) = self.apply_post_only(p_order)? {
+            let mut result = MatchingResult::new();
+            result.repriced_price_ = Some(repriced_price);
+            return Ok(Some(result));
+        }
 
-                match found_level {
-                    None => {
-                        temp_level.add_order(p_order);
-                        self.asks_.insert(temp_level);
-                    }
+        //FOK is checked before any fill, it is either fully filled or not touched at all
+        if p_order.time_in_force_ == TimeInForce::Fok && !self.fills_in_full(p_order) {
+            return Ok(None);
+        }
 
-                    Some(current_level) => {
-                        let mut copy_of_found_level = (*current_level).clone();
-                        copy_of_found_level.add_order(p_order);
-                        self.asks_.replace(copy_of_found_level);
-                    }
-                }
-            }
+        if let Some(breach_price) = self.band_breach(p_order) {
+            return self.apply_band_breach(p_order, breach_price);
         }
-        println!("After add_order {:#?}", self);
-    }
 
-    fn remove_order_by_id(&mut self, p_order: &Order) -> bool {
-        let level_order_match_or_none = self.get_level_match_from_id(p_order);
-        match level_order_match_or_none {
-            None => {
-                return false;
+        //Sweep the opposite side from the best price towards the limit price,
+        //every level is executed at its own price.
+        let mut result: Option<MatchingResult> = None;
+        let mut remaining_qty = p_order.qty_;
+        let self_match_policy = self.self_match_policy_;
+        let allocation = Arc::clone(&self.allocation_);
+        while !remaining_qty.is_zero() {
+            let found_level = self.get_level_match(p_order);
+            let Some(matched_level) = found_level else {
+                break;
+            };
+
+            println!("Matched to level {:?}", matched_level.price_);
+            let Some(level_result) = matched_level.match_order(
+                p_order,
+                remaining_qty,
+                self_match_policy,
+                &*allocation,
+            )?
+            else {
+                break;
+            };
+            let level_price = matched_level.price_;
+            let consumed_peaks: Vec<u64> = matched_level
+                .orders_
+                .iter()
+                .filter(|(_, order)| order.displayed_qty().is_zero())
+                .map(|(priority_seq, _)| *priority_seq)
+                .collect();
+            let orders_to_replenish: Vec<Order> = consumed_peaks
+                .iter()
+                .filter_map(|priority_seq| matched_level.remove_order(*priority_seq))
+                .collect();
+            if matched_level.orders_.is_empty() {
+                self.levels_mut(p_order.side_.opposite())
+                    .remove(&level_price);
             }
-            Some((matched_level, matched_order)) => {
-                let mut copy_of_found_level = (*matched_level).clone();
-                let copy_of_found_order = (*matched_order).clone();
-                if copy_of_found_level.remove_order(&copy_of_found_order) {
-                    match p_order.side_ {
-                        OrderSide::Buy => {
-                            if copy_of_found_level.orders_.is_empty() {
-                                return self.bids_.remove(&copy_of_found_level);
-                            } else {
-                                self.bids_.replace(copy_of_found_level);
-                                //TODO:: verify replaced element
-                            }
-                            return true;
-                        }
-                        OrderSide::Sell => {
-                            if copy_of_found_level.orders_.is_empty() {
-                                return self.asks_.remove(&copy_of_found_level);
-                            } else {
-                                self.asks_.replace(copy_of_found_level);
-                                //TODO:: verify replaced element
-                            }
-                            return true;
-                        }
-                    }
+            self.touch_level(p_order.side_.opposite(), level_price);
+            if !level_result.executed_qty_.is_zero() {
+                self.last_trade_price_ = Some(level_price);
+            }
+            for fill in &level_result.fills_ {
+                if fill.resting_leaves_qty_.is_zero() {
+                    self.order_index_.remove(&fill.resting_id_);
+                }
+            }
+            for report in &level_result.self_match_reports_ {
+                if report.resting_leaves_qty_.is_zero() {
+                    self.order_index_.remove(&report.resting_id_);
                 }
             }
+            //new peak from the hidden reserve goes to the back of the queue with a new priority
+            for mut order in orders_to_replenish {
+                println!("Order id {:?} replenished from reserve", order.id_);
+                self.add_order(&mut order);
+            }
+
+            if level_result.fills_.is_empty() && level_result.self_match_reports_.is_empty() {
+                break;
+            }
+            remaining_qty -= level_result.executed_qty_;
+            remaining_qty -= level_result.self_match_cancelled_qty();
+            match result.as_mut() {
+                None => result = Some(level_result),
+                Some(total_result) => total_result.merge(level_result),
+            }
         }
-        return false;
+        Ok(result)
     }
-}
 
-#[derive(Debug)]
-pub struct MatchingEngine {
-    order_book_by_symbol_: HashMap<String, OrderBook>,
-}
+    fn add_order(&mut self, p_order: &mut Order) {
+        //time priority is the arrival sequence in this book, never the entry_time_
+        self.last_priority_seq_ += 1;
+        p_order.priority_seq_ = self.last_priority_seq_;
+        if let Some(display_qty) = p_order.display_qty_ {
+            p_order.peak_qty_ = display_qty.min(p_order.qty_);
+        }
 
-impl MatchingEngine {
-    pub fn process_new_order(
+        self.order_index_.insert(
+            p_order.id_.to_owned(),
+            OrderLocation {
+                side_: p_order.side_,
+                price_: p_order.price_,
+                priority_seq_: p_order.priority_seq_,
+            },
+        );
+        self.levels_mut(p_order.side_)
+            .entry(p_order.price_)
+            .or_insert_with(|| Level::from_order(p_order))
+            .add_order(p_order);
+        self.touch_level(p_order.side_, p_order.price_);
+    }
+
+    fn add_or_cancel_remaining(
         &mut self,
         p_order: &mut Order,
-    ) -> Result<Option<MatchingResult>, String> {
-        let order_book_or_error = self.get_book_by_symbol(&p_order.symbol_);
-        match order_book_or_error {
-            None => {
-                if let Some(new_order_book) = self.add_order_book(&p_order.symbol_) {
-                    return new_order_book.add_first_order(p_order);
-                }
-                Err(String::from(
-                    "Failed to add first order in a order book of symbol {p_order.symbol_}",
-                ))
-            }
+        p_match_result: Option<MatchingResult>,
+    ) -> Option<MatchingResult> {
+        if let Some(match_result) = &p_match_result {
+            p_order.qty_ -= match_result.executed_qty_;
+            p_order.qty_ -= match_result.self_match_cancelled_qty();
+        }
+        if p_order.qty_.is_zero() {
+            return p_match_result;
+        }
 
-            Some(order_book) => {
-                let matching_result_or_none = order_book.match_order(p_order)?;
-                match matching_result_or_none {
-                    None => {
-                        order_book.add_order(p_order);
-                        println!("After add {:?}", self);
-                        Ok(None)
-                    }
-                    Some(match_result) => {
-                        p_order.qty_ -= match_result.executed_qty_;
-                        if p_order.qty_ > 0 {
-                            order_book.add_order(p_order);
-                        }
-                        println!(
-                            "Match result: {:?}, order qty: {} ",
-                            match_result, p_order.qty_
-                        );
-                        Ok(Some(match_result))
-                    }
-                }
-            }
+        //market, IOC and FOK orders never rest in the book, unfilled qty is cancelled
+        let can_rest = match p_order.type_ {
+            OrderType::Limit | OrderType::StopLimit => match p_order.time_in_force_ {
+                TimeInForce::Day | TimeInForce::Gtc | TimeInForce::Gtd(_) => true,
+                TimeInForce::Ioc | TimeInForce::Fok => false,
+            },
+            OrderType::Mkt | OrderType::Stop => false,
+        };
+        if can_rest {
+            self.add_order(p_order);
+            return p_match_result;
         }
+
+        let mut match_result = p_match_result.unwrap_or_else(MatchingResult::new);
+        match_result.cancelled_qty_ = p_order.qty_;
+        p_order.qty_ = Quantity::ZERO;
+        Some(match_result)
     }
 
-    pub fn process_rpl_order(
-        &mut self,
-        p_order: &mut Order,
-    ) -> Result<Option<MatchingResult>, String> {
-        let order_book_or_error = self.get_book_by_symbol(&p_order.symbol_);
-        match order_book_or_error {
-            None => {
-                Err(String::from(
-                    "Failed find the order book of symbol {p_order.symbol_}, replace on order failed",
-                ))
-            }
+    fn in_auction(&self) -> bool {
+        self.trading_state_ == TradingState::PreOpen
+    }
 
-            Some(order_book) => {
-                let order_removed = order_book.remove_order_by_id(p_order);
-                if !order_removed {
-                    return Err(String::from(
-                        "Failed to remove original order, replace failed",
-                    ));
-                }
+    fn is_crossed(&self) -> bool {
+        match (self.bids_.keys().next_back(), self.asks_.keys().next()) {
+            (Some(best_bid), Some(best_ask)) => best_bid >= best_ask,
+            _ => false,
+        }
+    }
 
-                let matching_result_or_none = order_book.match_order(p_order)?;
-                match matching_result_or_none {
-                    None => {
-                        order_book.add_order(p_order);
-                        println!("After add {:?}", self);
-                        Ok(None)
-                    }
-                    Some(match_result) => {
-                        p_order.qty_ -= match_result.executed_qty_;
-                        if p_order.qty_ > 0 {
-                            order_book.add_order(p_order);
-                        }
-                        println!(
-                            "Match result: {:?}, order qty: {} ",
-                            match_result, p_order.qty_
-                        );
-                        Ok(Some(match_result))
-                    }
-                }
-            }
+    //New orders and replaces are accepted in pre-open and open only
+    fn check_order_entry(&self, p_symbol: &String) -> Result<(), EngineError> {
+        match self.trading_state_ {
+            TradingState::PreOpen | TradingState::Open => Ok(()),
+            TradingState::Halted => Err(EngineError::BookHalted {
+                symbol_: p_symbol.to_owned(),
+            }),
+            TradingState::Closed => Err(EngineError::BookClosed {
+                symbol_: p_symbol.to_owned(),
+            }),
+        }
+    }
+
+    fn check_cancel(&self, p_symbol: &String) -> Result<(), EngineError> {
+        match self.trading_state_ {
+            TradingState::Closed => Err(EngineError::BookClosed {
+                symbol_: p_symbol.to_owned(),
+            }),
+            _ => Ok(()),
         }
     }
 
-    pub fn process_cxl_order(
+    //Entry point of a new order, stop orders wait in the trigger book unless the last trade
+    //already went through their stop price
+    fn process_order(
         &mut self,
         p_order: &mut Order,
-    ) -> Result<Option<MatchingResult>, String> {
-        let order_book_or_error = self.get_book_by_symbol(&p_order.symbol_);
-        match order_book_or_error {
-            None => {
-                Err(String::from(
-           "Failed find the order book of symbol {p_order.symbol_}, replace on order failed",
-          ))
+    ) -> Result<Option<MatchingResult>, EngineError> {
+        if self.in_auction()
+            && (matches!(p_order.type_, OrderType::Mkt)
+                || matches!(p_order.time_in_force_, TimeInForce::Ioc | TimeInForce::Fok))
+        {
+            return Err(EngineError::NotAcceptedInAuction {
+                order_id_: p_order.id_.to_owned(),
+            });
+        }
+
+        if matches!(p_order.type_, OrderType::Stop | OrderType::StopLimit) {
+            //stops are evaluated against trades only, an auction has none till the uncross
+            let is_triggered = !self.in_auction()
+                && self.last_trade_price_.is_some_and(|last_trade_price| {
+                    StopBook::is_triggered(p_order, last_trade_price)
+                });
+            if !is_triggered {
+                self.stop_book_.add_order(p_order);
+                return Ok(None);
             }
+            OrderBook::activate_stop_order(p_order);
+        }
 
-            Some(order_book) => {
-                let order_removed = order_book.remove_order_by_id(p_order);
-                if !order_removed {
-                    return Err(String::from(
-                        "Failed to remove original order, cancel failed",
-                    ));
+        if self.in_auction() {
+            self.add_order(p_order);
+            return Ok(None);
+        }
+
+        let matching_result_or_none = self.match_order(p_order)?;
+        let mut match_result = self.add_or_cancel_remaining(p_order, matching_result_or_none);
+        self.process_stop_triggers(&mut match_result);
+        Ok(match_result)
+    }
+
+    fn activate_stop_order(p_order: &mut Order) {
+        p_order.type_ = match p_order.type_ {
+            OrderType::Stop => OrderType::Mkt,
+            OrderType::StopLimit => OrderType::Limit,
+            order_type => order_type,
+        };
+    }
+
+    //Trades can trigger stop orders, whose trades can trigger more stop orders.
+    //Every trade printed by the event counts, not only the last one.
+    //Triggered orders are injected one by one in the order they were triggered,
+    //and the stops triggered by an injected order are queued behind the ones already waiting.
+    //The inbound event has traded already, a triggered stop never turns it into a reject.
+    //A volatility auction started by a stop ends the chain, the stops not injected yet go back
+    //to the trigger book and are evaluated again against the uncross price.
+    fn process_stop_triggers(&mut self, p_match_result: &mut Option<MatchingResult>) {
+        let mut triggered_orders = Vec::new();
+        let mut pending_orders: VecDeque<(u64, Order)> = VecDeque::new();
+        let mut printed_prices = p_match_result
+            .as_ref()
+            .and_then(|match_result| match_result.widen_printed_prices(None));
+        loop {
+            if let Some((lowest_price, highest_price)) =
+                printed_prices.filter(|_| !self.in_auction())
+            {
+                pending_orders.extend(self.stop_book_.take_triggered(lowest_price, highest_price));
+            }
+            let Some((stop_seq, stop_order)) = pending_orders.pop_front() else {
+                break;
+            };
+            if self.in_auction() {
+                self.stop_book_.put_back(stop_seq, stop_order);
+                continue;
+            }
+
+            println!("Stop order id {:?} triggered", stop_order.id_);
+            let mut order = stop_order.clone();
+            OrderBook::activate_stop_order(&mut order);
+            let matching_result_or_none = match self.match_order(&mut order) {
+                Ok(matching_result_or_none) => matching_result_or_none,
+                Err(reason) => {
+                    println!("Stop order id {:?} rejected: {}", order.id_, reason);
+                    triggered_orders.push(TriggeredOrder {
+                        seq_: EventSeq::default(),
+                        order_id_: order.id_,
+                        leaves_qty_: Quantity::ZERO,
+                        match_result_: None,
+                        reject_reason_: Some(reason),
+                    });
+                    continue;
                 }
-                //TODO:: retrigger matching of top BIDS and ASKS if top is cancelled
-                Ok(None)
+            };
+            //the stop breached the band and halted the book, nothing of it was filled
+            if self.in_auction() {
+                triggered_orders.push(TriggeredOrder {
+                    seq_: EventSeq::default(),
+                    order_id_: order.id_,
+                    leaves_qty_: stop_order.qty_,
+                    match_result_: matching_result_or_none,
+                    reject_reason_: None,
+                });
+                self.stop_book_.put_back(stop_seq, stop_order);
+                continue;
             }
+
+            let match_result = self.add_or_cancel_remaining(&mut order, matching_result_or_none);
+            if let Some(match_result) = &match_result {
+                printed_prices = match_result.widen_printed_prices(printed_prices);
+            }
+            triggered_orders.push(TriggeredOrder {
+                seq_: EventSeq::default(),
+                order_id_: order.id_,
+                leaves_qty_: order.qty_,
+                match_result_: match_result,
+                reject_reason_: None,
+            });
         }
-    }
 
-    pub fn contains(&self, p_symbol: &String) -> bool {
-        self.order_book_by_symbol_.contains_key(p_symbol)
+        if !triggered_orders.is_empty() {
+            p_match_result
+                .get_or_insert_with(MatchingResult::new)
+                .triggered_orders_ = triggered_orders;
+        }
     }
 
-    fn get_book_by_symbol(&mut self, p_symbol: &String) -> Option<&mut OrderBook> {
-        if let Some(mutable_order) = self.order_book_by_symbol_.get_mut(p_symbol) {
-            return Some(mutable_order);
+    //Removes every resting order for which p_should_remove is true
+    fn remove_orders_if(&mut self, p_should_remove: impl Fn(&Order) -> bool) -> Vec<Order> {
+        let order_ids: Vec<String> = self
+            .bids_
+            .values()
+            .chain(self.asks_.values())
+            .flat_map(|level| level.orders_.values())
+            .chain(self.stop_book_.orders())
+            .filter(|order| p_should_remove(order))
+            .map(|order| order.id_.to_owned())
+            .collect();
+
+        let mut removed_orders = Vec::new();
+        for order_id in order_ids {
+            let order = self
+                .get_order(&order_id)
+                .or_else(|| self.stop_book_.get_order(&order_id))
+                .cloned();
+            if let Some(order) = order {
+                if self.remove_order_by_id(&order_id) {
+                    removed_orders.push(order);
+                }
+            }
         }
-        None
+        removed_orders
     }
 
-    fn add_order_book(&mut self, p_symbol: &String) -> Option<&mut OrderBook> {
-        let new_order_book = OrderBook {
-            bids_: BTreeSet::new(),
-            asks_: BTreeSet::new(),
+    //Best p_max_levels levels of a side, best price first
+    fn depth(&self, p_side: OrderSide, p_max_levels: usize) -> Vec<DepthLevel> {
+        let levels: Box<dyn Iterator<Item = &Level>> = match p_side {
+            OrderSide::Buy => Box::new(self.bids_.values().rev()),
+            OrderSide::Sell => Box::new(self.asks_.values()),
         };
+        levels
+            .take(p_max_levels)
+            .map(|level| DepthLevel {
+                price_: level.price_,
+                qty_: level.displayed_qty(),
+                order_count_: level.orders_.len(),
+            })
+            .collect()
+    }
 
-        self.order_book_by_symbol_
-            .insert(p_symbol.to_owned(), new_order_book);
-        self.order_book_by_symbol_.get_mut(p_symbol)
+    fn get_order(&self, p_order_id: &String) -> Option<&Order> {
+        let location = self.order_index_.get(p_order_id)?;
+        let levels = match location.side_ {
+            OrderSide::Buy => &self.bids_,
+            OrderSide::Sell => &self.asks_,
+        };
+        levels
+            .get(&location.price_)?
+            .orders_
+            .get(&location.priority_seq_)
     }
-}
 
-pub fn process_event(
-    p_event_type: EventType,
-    p_order: &mut Order,
-    p_order_book_collection: &mut MatchingEngine,
-) -> Result<Option<MatchingResult>, String> {
-    match p_event_type {
-        EventType::New => {
-            println!("\nNew Order, received:\n\t {:?}", p_order);
-            p_order_book_collection.process_new_order(p_order)
+    //Exchange convention: qty reduction at the same price keeps the time priority,
+    //a price change or a qty increase loses it and the order is matched again like a new order.
+    fn amend_order(&mut self, p_order: &mut Order) -> Result<Option<MatchingResult>, EngineError> {
+        //a stop is amended in the trigger book, it is matched only once it is triggered
+        if self.stop_book_.amend_order(p_order) {
+            return Ok(None);
         }
-
-        EventType::Rpl => {
-            println!("\nReplace Order, received:\n\t {:?}", p_order);
-            p_order_book_collection.process_rpl_order(p_order)
+        let Some(resting_order) = self.get_order(&p_order.id_) else {
+            return Err(EngineError::UnknownOrder {
+                order_id_: p_order.id_.to_owned(),
+            });
+        };
+        if resting_order.price_ == p_order.price_
+            && p_order.qty_ <= resting_order.qty_
+            && !p_order.qty_.is_zero()
+        {
+            let mut amended_order = resting_order.clone();
+            amended_order.qty_ = p_order.qty_;
+            amended_order.peak_qty_ = amended_order.peak_qty_.min(p_order.qty_);
+            p_order.priority_seq_ = amended_order.priority_seq_;
+            println!("Order id {:?} amended in place", p_order.id_);
+            self.touch_level(p_order.side_, p_order.price_);
+            if let Some(level) = self.levels_mut(p_order.side_).get_mut(&p_order.price_) {
+                level.replace_or_remove(amended_order);
+            }
+            return Ok(None);
         }
 
-        EventType::Cxl => {
-            println!("\nCancel Order, received:\n\t {:?}", p_order);
-            p_order_book_collection.process_cxl_order(p_order)
+        //rejected before the original order leaves the book
+        if p_order.post_only_ == Some(PostOnly::Reject) && self.takes_liquidity(p_order) {
+            return Err(EngineError::PostOnlyWouldTrade {
+                order_id_: p_order.id_.to_owned(),
+                price_: p_order.price_,
+            });
         }
+        let band_reject = self
+            .price_band_
+            .is_some_and(|price_band| price_band.breach_action_ == BandBreachAction::Reject);
+        if let Some(breach_price) = self.band_breach(p_order).filter(|_| band_reject) {
+            return Err(EngineError::PriceBandBreach {
+                order_id_: p_order.id_.to_owned(),
+                price_: breach_price,
+            });
+        }
+
+        let order_removed = self.remove_order_by_id(&p_order.id_);
+        if !order_removed {
+            return Err(EngineError::UnknownOrder {
+                order_id_: p_order.id_.to_owned(),
+            });
+        }
+
+        if self.in_auction() {
+            self.add_order(p_order);
+            return Ok(None);
+        }
+
+        let matching_result_or_none = self.match_order(p_order)?;
+        Ok(self.add_or_cancel_remaining(p_order, matching_result_or_none))
+    }
+
+    fn remove_order_by_id(&mut self, p_order_id: &String) -> bool {
+        let Some(location) = self.order_index_.remove(p_order_id) else {
+            return self.stop_book_.remove_order_by_id(p_order_id).is_some();
+        };
+        self.touch_level(location.side_, location.price_);
+        let levels = self.levels_mut(location.side_);
+        let Some(level) = levels.get_mut(&location.price_) else {
+            return false;
+        };
+        let order_removed = level.remove_order(location.priority_seq_).is_some();
+        if level.orders_.is_empty() {
+            levels.remove(&location.price_);
+        }
+        order_removed
+    }
+}
+
+#[derive(Debug)]
+pub struct MatchingEngine {
+    order_book_by_symbol_: HashMap<String, OrderBook>,
+    //symbol of every resting order, cancel and replace requests only carry the order id
+    symbol_by_order_id_: HashMap<String, String>,
+    last_trade_id_: u64,
+    last_seq_num_: u64,
+    self_match_policy_: SelfMatchPolicy,
+    //Only the symbols of the registry are traded, each one has its order book from the start
+    instruments_: InstrumentRegistry,
+    //Stamps the entry time of new orders
+    clock_: Arc<dyn Clock>,
+    //entry times never go back, even when the clock restarts after a replay
+    last_entry_time_: u64,
+    //Write-ahead journal, every record is written before it is processed
+    journal_: Option<Journal>,
+    //Journal records reflected in the books, a snapshot is taken after this many records
+    journal_records_: u64,
+    bbo_subscribers_: Vec<Sender<BboUpdate>>,
+    depth_subscribers_: Vec<DepthSubscriber>,
+}
+
+impl Default for MatchingEngine {
+    fn default() -> Self {
+        MatchingEngine {
+            order_book_by_symbol_: HashMap::new(),
+            symbol_by_order_id_: HashMap::new(),
+            last_trade_id_: 0,
+            last_seq_num_: 0,
+            self_match_policy_: SelfMatchPolicy::default(),
+            instruments_: InstrumentRegistry::default(),
+            clock_: Arc::new(MonotonicClock::new()),
+            last_entry_time_: 0,
+            journal_: None,
+            journal_records_: 0,
+            bbo_subscribers_: Vec::new(),
+            depth_subscribers_: Vec::new(),
+        }
+    }
+}
+
+impl MatchingEngine {
+    pub fn new(p_instruments: InstrumentRegistry) -> Self {
+        MatchingEngine::with_clock(p_instruments, Arc::new(MonotonicClock::new()))
+    }
+
+    //Engine driven by p_clock, a ManualClock makes entry times deterministic
+    pub fn with_clock(p_instruments: InstrumentRegistry, p_clock: Arc<dyn Clock>) -> Self {
+        let mut engine = MatchingEngine {
+            instruments_: p_instruments,
+            clock_: p_clock,
+            ..Default::default()
+        };
+        let instruments: Vec<Instrument> = engine.instruments_.instruments().cloned().collect();
+        for instrument in &instruments {
+            engine.add_order_book(instrument);
+        }
+        engine
+    }
+
+    //Admin transition of the trading state of p_symbol, leaving pre-open uncrosses the auction
+    fn process_adm_request(
+        &mut self,
+        p_request: &AdminRequest,
+    ) -> Result<Option<MatchingResult>, EngineError> {
+        let Some(order_book) = self.get_book_by_symbol(&p_request.symbol_) else {
+            return Err(EngineError::UnknownSymbol {
+                symbol_: p_request.symbol_.to_owned(),
+            });
+        };
+        let previous_state = order_book.trading_state_;
+        let is_valid_transition = match (previous_state, p_request.trading_state_) {
+            (TradingState::Closed, TradingState::Halted) => false,
+            (previous_state, trading_state) => previous_state != trading_state,
+        };
+        if !is_valid_transition {
+            return Err(EngineError::InvalidStateTransition {
+                symbol_: p_request.symbol_.to_owned(),
+                from_: previous_state,
+                to_: p_request.trading_state_,
+            });
+        }
+
+        //orders collected in pre-open are uncrossed even when the book was halted or closed since
+        if p_request.trading_state_ == TradingState::Open
+            && (previous_state == TradingState::PreOpen || order_book.is_crossed())
+        {
+            return self.reopen_symbol(&p_request.symbol_, None, previous_state);
+        }
+        order_book.trading_state_ = p_request.trading_state_;
+        let mut match_result = None;
+        MatchingEngine::report_state_change(
+            &p_request.symbol_,
+            previous_state,
+            p_request.trading_state_,
+            &mut match_result,
+        );
+        Ok(match_result)
+    }
+
+    fn report_state_change(
+        p_symbol: &String,
+        p_previous_state: TradingState,
+        p_trading_state: TradingState,
+        p_match_result: &mut Option<MatchingResult>,
+    ) {
+        println!(
+            "Symbol {:?} moved from {:?} to {:?}",
+            p_symbol, p_previous_state, p_trading_state
+        );
+        p_match_result
+            .get_or_insert_with(MatchingResult::new)
+            .trading_state_change_ = Some(TradingStateChange {
+            seq_: EventSeq::default(),
+            symbol_: p_symbol.to_owned(),
+            previous_state_: p_previous_state,
+            trading_state_: p_trading_state,
+        });
+    }
+
+    //Price and volume the auction would uncross at now
+    pub fn indicative_uncross(
+        &self,
+        p_symbol: &String,
+        p_reference_price: Option<Price>,
+    ) -> Option<ClearingPrice> {
+        let order_book = self.order_book_by_symbol_.get(p_symbol)?;
+        let reference_price = p_reference_price.or(order_book.last_trade_price_);
+        order_book.clearing_price(reference_price)
+    }
+
+    //Ends the auction, crossing orders execute at a single clearing price and the book opens.
+    //Without p_reference_price the last trade price breaks the ties.
+    pub fn uncross_auction(
+        &mut self,
+        p_symbol: &String,
+        p_reference_price: Option<Price>,
+    ) -> Result<MatchingResult, Reject> {
+        self.write_ahead(|| JournalRecord::Uncross {
+            symbol_: p_symbol.to_owned(),
+            reference_price_: p_reference_price,
+        })?;
+        self.apply_uncross(p_symbol, p_reference_price)
+    }
+
+    fn apply_uncross(
+        &mut self,
+        p_symbol: &String,
+        p_reference_price: Option<Price>,
+    ) -> Result<MatchingResult, Reject> {
+        let result = self.uncross_symbol(p_symbol, p_reference_price);
+        self.sequence(Some(p_symbol), result)
+    }
+
+    fn uncross_symbol(
+        &mut self,
+        p_symbol: &String,
+        p_reference_price: Option<Price>,
+    ) -> Result<Option<MatchingResult>, EngineError> {
+        let Some(order_book) = self.get_book_by_symbol(p_symbol) else {
+            return Err(EngineError::UnknownSymbol {
+                symbol_: p_symbol.to_owned(),
+            });
+        };
+        if !order_book.in_auction() {
+            return Err(EngineError::NotInAuction {
+                symbol_: p_symbol.to_owned(),
+            });
+        }
+        self.reopen_symbol(p_symbol, p_reference_price, TradingState::PreOpen)
+    }
+
+    //Crossing orders execute at a single clearing price, then the book is open
+    fn reopen_symbol(
+        &mut self,
+        p_symbol: &String,
+        p_reference_price: Option<Price>,
+        p_previous_state: TradingState,
+    ) -> Result<Option<MatchingResult>, EngineError> {
+        let Some(order_book) = self.get_book_by_symbol(p_symbol) else {
+            return Err(EngineError::UnknownSymbol {
+                symbol_: p_symbol.to_owned(),
+            });
+        };
+        let reference_price = p_reference_price.or(order_book.last_trade_price_);
+        let mut match_result = order_book.uncross(reference_price)?;
+        MatchingEngine::report_state_change(
+            p_symbol,
+            p_previous_state,
+            TradingState::Open,
+            &mut match_result,
+        );
+
+        if let Some(match_result) = &match_result {
+            //buy orders executed or cancelled in full by the uncross
+            let aggressor_ids = match_result
+                .fills_
+                .iter()
+                .map(|fill| &fill.aggressor_id_)
+                .chain(
+                    match_result
+                        .self_match_reports_
+                        .iter()
+                        .map(|report| &report.aggressor_id_),
+                );
+            for aggressor_id in aggressor_ids {
+                let is_resting = self
+                    .order_book_by_symbol_
+                    .get(p_symbol)
+                    .is_some_and(|order_book| order_book.get_order(aggressor_id).is_some());
+                if !is_resting {
+                    self.symbol_by_order_id_.remove(aggressor_id);
+                }
+            }
+        }
+        self.update_match_state(p_symbol, &mut match_result);
+        Ok(match_result)
+    }
+
+    pub fn set_self_match_policy(&mut self, p_policy: SelfMatchPolicy) {
+        self.self_match_policy_ = p_policy;
+        for order_book in self.order_book_by_symbol_.values_mut() {
+            order_book.self_match_policy_ = p_policy;
+        }
+    }
+
+    fn process_new_order(
+        &mut self,
+        p_order: &mut Order,
+    ) -> Result<Option<MatchingResult>, EngineError> {
+        if self.symbol_by_order_id_.contains_key(&p_order.id_) {
+            return Err(EngineError::DuplicateOrderId {
+                order_id_: p_order.id_.to_owned(),
+            });
+        }
+        if p_order
+            .display_qty_
+            .is_some_and(|display_qty| display_qty.is_zero())
+        {
+            return Err(EngineError::InvalidDisplayQty {
+                order_id_: p_order.id_.to_owned(),
+            });
+        }
+        if p_order.post_only_.is_some() && !matches!(p_order.type_, OrderType::Limit) {
+            return Err(EngineError::PostOnlyNotLimit {
+                order_id_: p_order.id_.to_owned(),
+            });
+        }
+        let unknown_symbol = || EngineError::UnknownSymbol {
+            symbol_: p_order.symbol_.to_owned(),
+        };
+        let instrument = self
+            .instruments_
+            .get(&p_order.symbol_)
+            .ok_or_else(unknown_symbol)?;
+        instrument.validate_order(p_order)?;
+
+        let order_book = self
+            .order_book_by_symbol_
+            .get_mut(&p_order.symbol_)
+            .ok_or_else(unknown_symbol)?;
+        order_book.check_order_entry(&p_order.symbol_)?;
+        let mut match_result = order_book.process_order(p_order)?;
+        self.update_engine_state(p_order, &mut match_result);
+        println!(
+            "Match result: {:?}, order qty: {} ",
+            match_result, p_order.qty_
+        );
+        Ok(match_result)
+    }
+
+    fn process_rpl_order(
+        &mut self,
+        p_request: &ReplaceRequest,
+    ) -> Result<Option<MatchingResult>, EngineError> {
+        let symbol = self.get_symbol_by_order_id(&p_request.id_)?;
+        if symbol != p_request.symbol_ {
+            return Err(EngineError::SymbolChange {
+                order_id_: p_request.id_.to_owned(),
+            });
+        }
+
+        let Some(order_book) = self.order_book_by_symbol_.get_mut(&symbol) else {
+            return Err(EngineError::UnknownSymbol { symbol_: symbol });
+        };
+        order_book.check_order_entry(&symbol)?;
+        let original_order = order_book
+            .get_order(&p_request.id_)
+            .or_else(|| order_book.stop_book_.get_order(&p_request.id_));
+        let Some(original_order) = original_order else {
+            return Err(EngineError::UnknownOrder {
+                order_id_: p_request.id_.to_owned(),
+            });
+        };
+        if original_order.side_ != p_request.side_ {
+            return Err(EngineError::SideChange {
+                order_id_: p_request.id_.to_owned(),
+            });
+        }
+
+        let mut order = Order {
+            qty_: p_request.qty_,
+            price_: p_request.price_,
+            ..original_order.clone()
+        };
+        if let Some(instrument) = self.instruments_.get(&symbol) {
+            instrument.validate_order(&order)?;
+        }
+        let mut match_result = order_book.amend_order(&mut order)?;
+        order_book.process_stop_triggers(&mut match_result);
+        self.update_engine_state(&order, &mut match_result);
+        println!(
+            "Match result: {:?}, order qty: {} ",
+            match_result, order.qty_
+        );
+        Ok(match_result)
+    }
+
+    fn process_cxl_order(
+        &mut self,
+        p_request: &CancelRequest,
+    ) -> Result<Option<MatchingResult>, EngineError> {
+        let symbol = self.get_symbol_by_order_id(&p_request.id_)?;
+        let order_book_or_error = self.get_book_by_symbol(&symbol);
+        match order_book_or_error {
+            None => Err(EngineError::UnknownSymbol { symbol_: symbol }),
+
+            Some(order_book) => {
+                order_book.check_cancel(&symbol)?;
+                let order_removed = order_book.remove_order_by_id(&p_request.id_);
+                if !order_removed {
+                    return Err(EngineError::UnknownOrder {
+                        order_id_: p_request.id_.to_owned(),
+                    });
+                }
+                self.symbol_by_order_id_.remove(&p_request.id_);
+                //TODO:: retrigger matching of top BIDS and ASKS if top is cancelled
+                Ok(None)
+            }
+        }
+    }
+
+    //Called at the end of the trading day, every resting DAY order is cancelled
+    pub fn expire_day_orders(&mut self) -> Result<Vec<CancelReport>, EngineError> {
+        self.write_ahead(|| JournalRecord::ExpireDay)
+            .map_err(|reject| reject.reason_)?;
+        Ok(self.apply_expire_day())
+    }
+
+    //Cancels every resting GTD order whose expiry time is reached at p_now
+    pub fn expire_gtd_orders(
+        &mut self,
+        p_now: SystemTime,
+    ) -> Result<Vec<CancelReport>, EngineError> {
+        self.write_ahead(|| JournalRecord::ExpireGtd { now_: p_now })
+            .map_err(|reject| reject.reason_)?;
+        Ok(self.apply_expire_gtd(p_now))
+    }
+
+    fn apply_expire_day(&mut self) -> Vec<CancelReport> {
+        self.expire_orders(CancelReason::DayExpired, |order| {
+            order.time_in_force_ == TimeInForce::Day
+        })
+    }
+
+    fn apply_expire_gtd(&mut self, p_now: SystemTime) -> Vec<CancelReport> {
+        self.expire_orders(CancelReason::GtdExpired, |order| {
+            match order.time_in_force_ {
+                TimeInForce::Gtd(expire_time) => expire_time <= p_now,
+                _ => false,
+            }
+        })
+    }
+
+    fn expire_orders(
+        &mut self,
+        p_reason: CancelReason,
+        p_is_expired: impl Fn(&Order) -> bool,
+    ) -> Vec<CancelReport> {
+        //symbols are sorted so that the cancel reports come out in a deterministic order
+        let mut symbols: Vec<String> = self.order_book_by_symbol_.keys().cloned().collect();
+        symbols.sort();
+
+        let mut cancel_reports = Vec::new();
+        for symbol in symbols {
+            let Some(order_book) = self.order_book_by_symbol_.get_mut(&symbol) else {
+                continue;
+            };
+            for order in order_book.remove_orders_if(&p_is_expired) {
+                self.symbol_by_order_id_.remove(&order.id_);
+                let seq = self.next_seq(Some(&symbol));
+                cancel_reports.push(CancelReport {
+                    seq_: seq,
+                    order_id_: order.id_,
+                    symbol_: symbol.to_owned(),
+                    cancelled_qty_: order.qty_,
+                    reason_: p_reason,
+                });
+            }
+            self.publish_market_data(&symbol, &[]);
+        }
+        cancel_reports
+    }
+
+    pub fn depth(
+        &self,
+        p_symbol: &String,
+        p_side: OrderSide,
+        p_max_levels: usize,
+    ) -> Vec<DepthLevel> {
+        match self.order_book_by_symbol_.get(p_symbol) {
+            None => Vec::new(),
+            Some(order_book) => order_book.depth(p_side, p_max_levels),
+        }
+    }
+
+    pub fn contains(&self, p_symbol: &String) -> bool {
+        self.order_book_by_symbol_.contains_key(p_symbol)
+    }
+
+    //Rebuilds the books by replaying the journal at p_path, then every new event is journaled
+    //there before it is processed. The engine must be fresh, or restored from a snapshot of this
+    //journal, and configured like the one that wrote the journal. The replay then ends with the
+    //same books and sequence numbers. Returns the number of replayed records.
+    pub fn open_journal(
+        &mut self,
+        p_path: &Path,
+        p_fsync_policy: FsyncPolicy,
+    ) -> io::Result<usize> {
+        let mut journal = Journal::open(p_path, p_fsync_policy)?;
+        let records = journal.read_records()?;
+        let Some(tail_len) = (records.len() as u64).checked_sub(self.journal_records_) else {
+            return Err(codec::invalid_data(format!(
+                "Journal has {} records, the books already reflect {}",
+                records.len(),
+                self.journal_records_
+            )));
+        };
+        let replayed_records = tail_len as usize;
+        //records before the tail are in the snapshot the engine was restored from
+        let tail = records.into_iter().skip(self.journal_records_ as usize);
+        self.journal_records_ += tail_len;
+        //outputs were published before the crash, replay only rebuilds the state
+        for record in tail {
+            match record {
+                JournalRecord::Event {
+                    entry_time_,
+                    event_,
+                } => {
+                    let _ = self.apply_event(entry_time_, event_);
+                }
+                JournalRecord::Uncross {
+                    symbol_,
+                    reference_price_,
+                } => {
+                    let _ = self.apply_uncross(&symbol_, reference_price_);
+                }
+                JournalRecord::ExpireDay => {
+                    self.apply_expire_day();
+                }
+                JournalRecord::ExpireGtd { now_ } => {
+                    self.apply_expire_gtd(now_);
+                }
+            }
+        }
+        self.journal_ = Some(journal);
+        Ok(replayed_records)
+    }
+
+    //A record that can not be journaled is not processed and takes no sequence number
+    fn write_ahead(&mut self, p_record: impl FnOnce() -> JournalRecord) -> Result<(), Reject> {
+        let Some(journal) = &mut self.journal_ else {
+            return Ok(());
+        };
+        journal.append(&p_record()).map_err(|error| Reject {
+            seq_: EventSeq::default(),
+            reason_: EngineError::JournalWriteFailed {
+                reason_: error.to_string(),
+            },
+        })?;
+        self.journal_records_ += 1;
+        Ok(())
+    }
+
+    fn next_entry_time(&self) -> u64 {
+        self.clock_.now_nanos().max(self.last_entry_time_)
+    }
+
+    fn apply_event(
+        &mut self,
+        p_entry_time: u64,
+        p_event_type: EventType,
+    ) -> Result<MatchingResult, Reject> {
+        self.last_entry_time_ = self.last_entry_time_.max(p_entry_time);
+        let symbol = match &p_event_type {
+            EventType::New(order) => Some(order.symbol_.to_owned()),
+            EventType::Rpl(replace_request) => Some(replace_request.symbol_.to_owned()),
+            EventType::Cxl(cancel_request) => {
+                self.symbol_by_order_id_.get(&cancel_request.id_).cloned()
+            }
+            EventType::Adm(admin_request) => Some(admin_request.symbol_.to_owned()),
+        };
+        let result = match p_event_type {
+            EventType::New(mut order) => {
+                println!("\nNew Order, received:\n\t {:?}", order);
+                order.entry_time_ = p_entry_time;
+                self.process_new_order(&mut order)
+            }
+
+            EventType::Rpl(replace_request) => {
+                println!("\nReplace Order, received:\n\t {:?}", replace_request);
+                self.process_rpl_order(&replace_request)
+            }
+
+            EventType::Cxl(cancel_request) => {
+                println!("\nCancel Order, received:\n\t {:?}", cancel_request);
+                self.process_cxl_order(&cancel_request)
+            }
+
+            EventType::Adm(admin_request) => {
+                println!("\nAdmin request, received:\n\t {:?}", admin_request);
+                self.process_adm_request(&admin_request)
+            }
+        };
+        self.sequence(symbol.as_ref(), result)
+    }
+
+    pub fn last_seq_num(&self) -> u64 {
+        self.last_seq_num_
+    }
+
+    pub fn last_symbol_seq_num(&self, p_symbol: &String) -> Option<u64> {
+        self.order_book_by_symbol_
+            .get(p_symbol)
+            .map(|order_book| order_book.last_seq_num_)
+    }
+
+    fn next_seq(&mut self, p_symbol: Option<&String>) -> EventSeq {
+        self.last_seq_num_ += 1;
+        let order_book = p_symbol.and_then(|symbol| self.order_book_by_symbol_.get_mut(symbol));
+        let symbol_seq_num = match order_book {
+            None => 0,
+            Some(order_book) => {
+                order_book.last_seq_num_ += 1;
+                order_book.last_seq_num_
+            }
+        };
+        EventSeq {
+            seq_num_: self.last_seq_num_,
+            symbol_seq_num_: symbol_seq_num,
+        }
+    }
+
+    //Acks or rejects one inbound event, then numbers every output the event caused
+    fn sequence(
+        &mut self,
+        p_symbol: Option<&String>,
+        p_result: Result<Option<MatchingResult>, EngineError>,
+    ) -> Result<MatchingResult, Reject> {
+        let seq = self.next_seq(p_symbol);
+        let mut trading_state_changes = Vec::new();
+        let result = match p_result {
+            Err(reason) => Err(Reject {
+                seq_: seq,
+                reason_: reason,
+            }),
+            Ok(match_result) => {
+                let mut match_result = match_result.unwrap_or_else(MatchingResult::new);
+                match_result.seq_ = seq;
+                self.sequence_outputs(p_symbol, &mut match_result);
+                trading_state_changes = match_result.trading_state_changes();
+                Ok(match_result)
+            }
+        };
+        if let Some(symbol) = p_symbol {
+            self.publish_market_data(symbol, &trading_state_changes);
+        }
+        result
+    }
+
+    //State change first, then the trades and self-match cancels of the match,
+    //then every triggered stop order followed by its own outputs
+    fn sequence_outputs(&mut self, p_symbol: Option<&String>, p_match_result: &mut MatchingResult) {
+        if let Some(trading_state_change) = &mut p_match_result.trading_state_change_ {
+            trading_state_change.seq_ = self.next_seq(p_symbol);
+        }
+        for fill in &mut p_match_result.fills_ {
+            fill.seq_ = self.next_seq(p_symbol);
+        }
+        for report in &mut p_match_result.self_match_reports_ {
+            report.seq_ = self.next_seq(p_symbol);
+        }
+        for triggered_order in &mut p_match_result.triggered_orders_ {
+            triggered_order.seq_ = self.next_seq(p_symbol);
+            if let Some(match_result) = &mut triggered_order.match_result_ {
+                self.sequence_outputs(p_symbol, match_result);
+            }
+        }
+    }
+
+    fn get_book_by_symbol(&mut self, p_symbol: &String) -> Option<&mut OrderBook> {
+        if let Some(mutable_order) = self.order_book_by_symbol_.get_mut(p_symbol) {
+            return Some(mutable_order);
+        }
+        None
+    }
+
+    fn get_symbol_by_order_id(&self, p_order_id: &String) -> Result<String, EngineError> {
+        match self.symbol_by_order_id_.get(p_order_id) {
+            None => Err(EngineError::UnknownOrder {
+                order_id_: p_order_id.to_owned(),
+            }),
+            Some(symbol) => Ok(symbol.to_owned()),
+        }
+    }
+
+    //Trade ids are unique across all the order books of this engine,
+    //symbol_by_order_id_ keeps only the orders that are still resting
+    fn update_engine_state(
+        &mut self,
+        p_order: &Order,
+        p_match_result: &mut Option<MatchingResult>,
+    ) {
+        self.update_order_state(&p_order.id_, &p_order.symbol_, p_order.qty_, p_match_result);
+    }
+
+    fn update_order_state(
+        &mut self,
+        p_order_id: &String,
+        p_symbol: &String,
+        p_leaves_qty: Quantity,
+        p_match_result: &mut Option<MatchingResult>,
+    ) {
+        self.update_fills_state(p_match_result);
+
+        if p_leaves_qty.is_zero() {
+            self.symbol_by_order_id_.remove(p_order_id);
+        } else {
+            self.symbol_by_order_id_
+                .insert(p_order_id.to_owned(), p_symbol.to_owned());
+        }
+
+        self.update_triggered_state(p_symbol, p_match_result);
+    }
+
+    //Fills and triggered orders of a match that has no single incoming order
+    fn update_match_state(
+        &mut self,
+        p_symbol: &String,
+        p_match_result: &mut Option<MatchingResult>,
+    ) {
+        self.update_fills_state(p_match_result);
+        self.update_triggered_state(p_symbol, p_match_result);
+    }
+
+    fn update_fills_state(&mut self, p_match_result: &mut Option<MatchingResult>) {
+        if let Some(match_result) = p_match_result {
+            for fill in &mut match_result.fills_ {
+                self.last_trade_id_ += 1;
+                fill.trade_id_ = self.last_trade_id_;
+                if fill.resting_leaves_qty_.is_zero() {
+                    self.symbol_by_order_id_.remove(&fill.resting_id_);
+                }
+            }
+            for report in &match_result.self_match_reports_ {
+                if report.resting_leaves_qty_.is_zero() {
+                    self.symbol_by_order_id_.remove(&report.resting_id_);
+                }
+            }
+        }
+    }
+
+    fn update_triggered_state(
+        &mut self,
+        p_symbol: &String,
+        p_match_result: &mut Option<MatchingResult>,
+    ) {
+        if let Some(match_result) = p_match_result {
+            for triggered_order in &mut match_result.triggered_orders_ {
+                self.update_order_state(
+                    &triggered_order.order_id_,
+                    p_symbol,
+                    triggered_order.leaves_qty_,
+                    &mut triggered_order.match_result_,
+                );
+            }
+        }
+    }
+
+    fn add_order_book(&mut self, p_instrument: &Instrument) {
+        let new_order_book = OrderBook {
+            bids_: BTreeMap::new(),
+            asks_: BTreeMap::new(),
+            order_index_: HashMap::new(),
+            last_priority_seq_: 0,
+            stop_book_: StopBook::default(),
+            last_trade_price_: None,
+            tick_size_: p_instrument.tick_size_,
+            self_match_policy_: self.self_match_policy_,
+            allocation_: Arc::clone(&p_instrument.allocation_),
+            trading_state_: TradingState::Open,
+            price_band_: p_instrument.price_band_,
+            last_seq_num_: 0,
+            published_bbo_: (None, None),
+            touched_bids_: BTreeSet::new(),
+            touched_asks_: BTreeSet::new(),
+        };
+
+        self.order_book_by_symbol_
+            .insert(p_instrument.symbol_.to_owned(), new_order_book);
     }
 }
 
+//Every accepted event is acked by a MatchingResult and every refused one by a Reject,
+//both carry the sequence numbers of the event and of everything it caused
+pub fn process_event(
+    p_event_type: EventType,
+    p_order_book_collection: &mut MatchingEngine,
+) -> Result<MatchingResult, Reject> {
+    let entry_time = p_order_book_collection.next_entry_time();
+    p_order_book_collection.write_ahead(|| JournalRecord::Event {
+        entry_time_: entry_time,
+        event_: p_event_type.clone(),
+    })?;
+    p_order_book_collection.apply_event(entry_time, p_event_type)
+}
+
 #[cfg(test)]
 mod test {
 
@@ -523,601 +1811,3701 @@ mod test {
      *   - Simple match with limit order (price match)
      *   - Best Price priority Then time priority (TODO:: More test cases)
      *   - Best Price for bids vs best price for sells
-     *   - First order is mkt (no match found) => TODO::
+     *   - First order is mkt (no match found), it is cancelled and never rests
      *   - Match with Multiple orders : TODO:: Testing
      *   -
      *   -
      */
 
     use super::*;
+    use clock::ManualClock;
+    use journal::FsyncPolicy;
+    use market_data::{BboUpdate, DepthMessage};
+
+    const PRICE_SCALE: u32 = 2;
+
+    fn px(p_price: f64) -> Price {
+        Price::from_f64(p_price, PRICE_SCALE).unwrap()
+    }
+
+    fn test_instrument(p_symbol: &str) -> Instrument {
+        Instrument {
+            symbol_: String::from(p_symbol),
+            price_scale_: PRICE_SCALE,
+            ..Default::default()
+        }
+    }
+
+    fn test_registry(p_instruments: Vec<Instrument>) -> InstrumentRegistry {
+        let mut instrument_registry = InstrumentRegistry::new();
+        for instrument in p_instruments {
+            instrument_registry.add_instrument(instrument);
+        }
+        instrument_registry
+    }
+
+    fn engine_with(p_instruments: Vec<Instrument>) -> MatchingEngine {
+        MatchingEngine::new(test_registry(p_instruments))
+    }
+
+    fn test_engine() -> MatchingEngine {
+        engine_with(vec![test_instrument("REL"), test_instrument("TCS")])
+    }
 
     fn validate_result(
-        p_result: &Result<Option<MatchingResult>, String>,
-        p_exp_exec_qty: i32,
-        p_exp_exec_price: f32,
+        p_result: &Result<MatchingResult, Reject>,
+        p_exp_exec_qty: u64,
+        p_exp_exec_price: Price,
         p_matched_order_ids: Option<&Vec<String>>,
     ) {
         match p_result {
-            Ok(match_result_or_none) => match match_result_or_none {
-                None => {
-                    assert!(p_exp_exec_qty == 0);
-                }
-                Some(match_result) => {
-                    assert_eq!(match_result.executed_qty_, p_exp_exec_qty);
-                    match p_matched_order_ids {
-                        None => {
-                            assert!(match_result.matched_order_ids_.is_empty());
-                        }
-                        Some(matched_ord_ids) => {
-                            assert_eq!(match_result.executed_price_, p_exp_exec_price);
-                            assert_eq!(&match_result.matched_order_ids_, matched_ord_ids);
-                        }
+            Ok(match_result) => {
+                let exp_exec_qty = Quantity(p_exp_exec_qty);
+                assert_eq!(match_result.executed_qty_, exp_exec_qty);
+                let matched_order_ids: Vec<String> = match_result
+                    .fills_
+                    .iter()
+                    .map(|fill| fill.resting_id_.to_owned())
+                    .collect();
+                match p_matched_order_ids {
+                    None => {
+                        assert!(matched_order_ids.is_empty());
+                    }
+                    Some(matched_ord_ids) => {
+                        //avg price is checked on the exact notional
+                        assert_eq!(
+                            match_result.executed_notional(),
+                            p_exp_exec_price.notional(exp_exec_qty)
+                        );
+                        assert_eq!(&matched_order_ids, matched_ord_ids);
                     }
                 }
-            },
+            }
             Err(error_msg) => {
-                println!("process event failed with error {error_msg}");
-                assert!(false);
+                panic!("process event failed with error {error_msg}");
             }
         }
     }
 
     #[test]
     fn create_first_order() {
-        let mut order_book_collection = MatchingEngine {
-            order_book_by_symbol_: HashMap::new(),
-        };
+        let mut order_book_collection = test_engine();
 
-        let mut order = Order {
+        let order = Order {
             id_: String::from("1"),
-            price_: 100.0,
+            price_: px(100.0),
             symbol_: String::from("REL"),
-            qty_: 200,
+            qty_: Quantity(200),
             side_: OrderSide::Buy,
             type_: OrderType::Limit,
-            entry_time_: std::time::SystemTime::now(),
+            ..Default::default()
         };
-        let result = process_event(EventType::New, &mut order, &mut order_book_collection);
-        validate_result(&result, 0, 0.0, None);
+        let result = process_event(EventType::New(order), &mut order_book_collection);
+        validate_result(&result, 0, px(0.0), None);
     }
 
     #[test]
     fn qty_match_simple_order() {
-        let mut order_book_collection = MatchingEngine {
-            order_book_by_symbol_: HashMap::new(),
-        };
+        let mut order_book_collection = test_engine();
 
         let mut matched_order_ids = Vec::new();
-        let mut order = Order {
+        let order = Order {
             id_: String::from("1"),
-            price_: 100.0,
+            price_: px(100.0),
             symbol_: String::from("REL"),
-            qty_: 200,
+            qty_: Quantity(200),
             side_: OrderSide::Buy,
             type_: OrderType::Limit,
-            entry_time_: std::time::SystemTime::now(),
+            ..Default::default()
         };
-        let result = process_event(EventType::New, &mut order, &mut order_book_collection);
-        validate_result(&result, 0, 0.0, None);
+        let result = process_event(EventType::New(order), &mut order_book_collection);
+        validate_result(&result, 0, px(0.0), None);
 
-        let mut order = Order {
+        let order = Order {
             id_: String::from("2"),
-            price_: 100.0,
+            price_: px(100.0),
             symbol_: String::from("REL"),
-            qty_: 200,
+            qty_: Quantity(200),
             side_: OrderSide::Sell,
             type_: OrderType::Limit,
-            entry_time_: std::time::SystemTime::now(),
+            ..Default::default()
         };
         matched_order_ids.push("1".to_string());
-        let result = process_event(EventType::New, &mut order, &mut order_book_collection);
-        validate_result(&result, 200, order.price_, Some(&matched_order_ids));
+        let result = process_event(EventType::New(order), &mut order_book_collection);
+        validate_result(&result, 200, px(100.0), Some(&matched_order_ids));
 
-        let mut order = Order {
+        let order = Order {
             id_: String::from("3"),
-            price_: 100.0,
+            price_: px(100.0),
             symbol_: String::from("REL"),
-            qty_: 200,
+            qty_: Quantity(200),
             side_: OrderSide::Sell,
             type_: OrderType::Limit,
-            entry_time_: std::time::SystemTime::now(),
+            ..Default::default()
         };
-        let result = process_event(EventType::New, &mut order, &mut order_book_collection);
-        validate_result(&result, 0, 0.0, None);
+        let result = process_event(EventType::New(order), &mut order_book_collection);
+        validate_result(&result, 0, px(0.0), None);
 
-        let mut order = Order {
+        let order = Order {
             id_: String::from("4"),
-            price_: 100.0,
+            price_: px(100.0),
             symbol_: String::from("REL"),
-            qty_: 200,
+            qty_: Quantity(200),
             side_: OrderSide::Buy,
             type_: OrderType::Limit,
-            entry_time_: std::time::SystemTime::now(),
+            ..Default::default()
         };
         matched_order_ids.clear();
         matched_order_ids.push("3".to_string());
-        let result = process_event(EventType::New, &mut order, &mut order_book_collection);
-        validate_result(&result, 200, order.price_, Some(&matched_order_ids));
+        let result = process_event(EventType::New(order), &mut order_book_collection);
+        validate_result(&result, 200, px(100.0), Some(&matched_order_ids));
     }
 
     #[test]
     fn qty_macth_test_partial_match() {
-        let mut order_book_collection = MatchingEngine {
-            order_book_by_symbol_: HashMap::new(),
-        };
+        let mut order_book_collection = test_engine();
         let mut matched_order_ids = Vec::new();
 
-        let mut order = Order {
+        let order = Order {
             id_: String::from("1"),
-            price_: 100.0,
+            price_: px(100.0),
             symbol_: String::from("REL"),
-            qty_: 200,
+            qty_: Quantity(200),
             side_: OrderSide::Buy,
             type_: OrderType::Limit,
-            entry_time_: std::time::SystemTime::now(),
+            ..Default::default()
         };
-        let result = process_event(EventType::New, &mut order, &mut order_book_collection);
+        let result = process_event(EventType::New(order), &mut order_book_collection);
         //200 added to book, exected 0;
-        validate_result(&result, 0, 0.0, None);
+        validate_result(&result, 0, px(0.0), None);
 
-        let mut order = Order {
+        let order = Order {
             id_: String::from("2"),
-            price_: 100.0,
+            price_: px(100.0),
             symbol_: String::from("REL"),
-            qty_: 100,
+            qty_: Quantity(100),
             side_: OrderSide::Sell,
             type_: OrderType::Limit,
-            entry_time_: std::time::SystemTime::now(),
+            ..Default::default()
         };
-        let result = process_event(EventType::New, &mut order, &mut order_book_collection);
+        let result = process_event(EventType::New(order), &mut order_book_collection);
         //100 partially executed, 100 buy left in book
         matched_order_ids.clear();
         matched_order_ids.push("1".to_string());
-        validate_result(&result, 100, order.price_, Some(&matched_order_ids));
+        validate_result(&result, 100, px(100.0), Some(&matched_order_ids));
 
-        let mut order = Order {
+        let order = Order {
             id_: String::from("3"),
-            price_: 100.0,
+            price_: px(100.0),
             symbol_: String::from("REL"),
-            qty_: 200,
+            qty_: Quantity(200),
             side_: OrderSide::Sell,
             type_: OrderType::Limit,
-            entry_time_: std::time::SystemTime::now(),
+            ..Default::default()
         };
-        let result = process_event(EventType::New, &mut order, &mut order_book_collection);
+        let result = process_event(EventType::New(order), &mut order_book_collection);
         //100 executed, 100 sell id 3 left in book
-        validate_result(&result, 100, order.price_, Some(&matched_order_ids));
+        validate_result(&result, 100, px(100.0), Some(&matched_order_ids));
 
-        let mut order = Order {
+        let order = Order {
             id_: String::from("4"),
-            price_: 100.0,
+            price_: px(100.0),
             symbol_: String::from("REL"),
-            qty_: 100,
+            qty_: Quantity(100),
             side_: OrderSide::Buy,
             type_: OrderType::Limit,
-            entry_time_: std::time::SystemTime::now(),
+            ..Default::default()
         };
-        let result = process_event(EventType::New, &mut order, &mut order_book_collection);
+        let result = process_event(EventType::New(order), &mut order_book_collection);
         //100 executed, nothing left in book
         matched_order_ids.clear();
         matched_order_ids.push("3".to_string());
-        validate_result(&result, 100, order.price_, Some(&matched_order_ids));
+        validate_result(&result, 100, px(100.0), Some(&matched_order_ids));
 
-        let mut order = Order {
+        let order = Order {
             id_: String::from("5"),
-            price_: 100.0,
+            price_: px(100.0),
             symbol_: String::from("REL"),
-            qty_: 200,
+            qty_: Quantity(200),
             side_: OrderSide::Buy,
             type_: OrderType::Limit,
-            entry_time_: std::time::SystemTime::now(),
+            ..Default::default()
         };
-        let result = process_event(EventType::New, &mut order, &mut order_book_collection);
+        let result = process_event(EventType::New(order), &mut order_book_collection);
         //200 buy added in book, nothing executed
         matched_order_ids.clear();
-        validate_result(&result, 0, 0.0, None);
+        validate_result(&result, 0, px(0.0), None);
 
-        let mut order = Order {
+        let order = Order {
             id_: String::from("6"),
-            price_: 100.0,
+            price_: px(100.0),
             symbol_: String::from("REL"),
-            qty_: 200,
+            qty_: Quantity(200),
             side_: OrderSide::Sell,
             type_: OrderType::Limit,
-            entry_time_: std::time::SystemTime::now(),
+            ..Default::default()
         };
-        let result = process_event(EventType::New, &mut order, &mut order_book_collection);
+        let result = process_event(EventType::New(order), &mut order_book_collection);
         //200 buy sell matched, nothin left in book
         matched_order_ids.push("5".to_string());
-        validate_result(&result, 200, order.price_, Some(&matched_order_ids));
+        validate_result(&result, 200, px(100.0), Some(&matched_order_ids));
     }
 
     #[test]
     fn mkt_order_match_simple() {
-        let mut order_book_collection = MatchingEngine {
-            order_book_by_symbol_: HashMap::new(),
-        };
+        let mut order_book_collection = test_engine();
         let mut matched_order_ids = Vec::new();
 
-        let mut order = Order {
+        let order = Order {
             id_: String::from("1"),
-            price_: 100.0,
+            price_: px(100.0),
             symbol_: String::from("REL"),
-            qty_: 200,
+            qty_: Quantity(200),
             side_: OrderSide::Buy,
             type_: OrderType::Limit,
-            entry_time_: std::time::SystemTime::now(),
+            ..Default::default()
         };
-        let result = process_event(EventType::New, &mut order, &mut order_book_collection);
+        let result = process_event(EventType::New(order), &mut order_book_collection);
         //200@100 buy added to book
-        validate_result(&result, 0, 0.0, None);
+        validate_result(&result, 0, px(0.0), None);
 
-        let mut order = Order {
+        let order = Order {
             id_: String::from("2"),
-            price_: 0.0,
+            price_: px(0.0),
             symbol_: String::from("REL"),
-            qty_: 200,
+            qty_: Quantity(200),
             side_: OrderSide::Sell,
             type_: OrderType::Mkt,
-            entry_time_: std::time::SystemTime::now(),
+            ..Default::default()
         };
-        let result = process_event(EventType::New, &mut order, &mut order_book_collection);
+        let result = process_event(EventType::New(order), &mut order_book_collection);
         //mkt matched
         matched_order_ids.push("1".to_string());
-        validate_result(&result, 200, 100.0, Some(&matched_order_ids));
+        validate_result(&result, 200, px(100.0), Some(&matched_order_ids));
 
-        let mut order = Order {
+        let order = Order {
             id_: String::from("3"),
-            price_: 100.0,
+            price_: px(100.0),
             symbol_: String::from("REL"),
-            qty_: 200,
+            qty_: Quantity(200),
             side_: OrderSide::Buy,
             type_: OrderType::Limit,
-            entry_time_: std::time::SystemTime::now(),
+            ..Default::default()
         };
-        let result = process_event(EventType::New, &mut order, &mut order_book_collection);
+        let result = process_event(EventType::New(order), &mut order_book_collection);
         //200@100 buy added to book
-        validate_result(&result, 0, 0.0, None);
+        validate_result(&result, 0, px(0.0), None);
 
-        let mut order = Order {
+        let order = Order {
             id_: String::from("4"),
-            price_: 0.0,
+            price_: px(0.0),
             symbol_: String::from("REL"),
-            qty_: 200,
+            qty_: Quantity(200),
             side_: OrderSide::Buy,
             type_: OrderType::Mkt,
-            entry_time_: std::time::SystemTime::now(),
+            ..Default::default()
         };
-        let result = process_event(EventType::New, &mut order, &mut order_book_collection);
-        //another 200@100 added into book but not matched
-        validate_result(&result, 0, 0.0, None);
+        let result = process_event(EventType::New(order), &mut order_book_collection);
+        //nothing to match on the sell side, mkt order is cancelled and not added into book
+        validate_result(&result, 0, px(0.0), None);
+        assert_eq!(result.unwrap().cancelled_qty_, Quantity(200));
+        assert!(order_book_collection
+            .get_symbol_by_order_id(&String::from("4"))
+            .is_err());
+
+        let order_book = order_book_collection
+            .get_book_by_symbol(&String::from("REL"))
+            .unwrap();
+        assert!(order_book.asks_.is_empty());
+        assert_eq!(order_book.bids_.len(), 1);
+        assert_eq!(
+            order_book.best_level(OrderSide::Buy).unwrap().orders_.len(),
+            1
+        );
     }
 
     #[test]
     fn mkt_order_match_time() {
-        let mut order_book_collection = MatchingEngine {
-            order_book_by_symbol_: HashMap::new(),
-        };
+        let mut order_book_collection = test_engine();
         let mut matched_order_ids = Vec::new();
-        let mut order = Order {
+        let order = Order {
             id_: String::from("1"),
-            price_: 100.0,
+            price_: px(100.0),
             symbol_: String::from("REL"),
-            qty_: 200,
+            qty_: Quantity(200),
             side_: OrderSide::Buy,
             type_: OrderType::Limit,
-            entry_time_: std::time::SystemTime::now(),
+            ..Default::default()
         };
-        let result = process_event(EventType::New, &mut order, &mut order_book_collection);
+        let result = process_event(EventType::New(order), &mut order_book_collection);
         //200@100 buy added to book
-        validate_result(&result, 0, 0.0, None);
+        validate_result(&result, 0, px(0.0), None);
 
-        let mut order = Order {
+        let order = Order {
             id_: String::from("2"),
-            price_: 100.0,
+            price_: px(100.0),
             symbol_: String::from("REL"),
-            qty_: 200,
+            qty_: Quantity(200),
             side_: OrderSide::Buy,
             type_: OrderType::Limit,
-            entry_time_: std::time::SystemTime::now(),
+            ..Default::default()
         };
-        let result = process_event(EventType::New, &mut order, &mut order_book_collection);
+        let result = process_event(EventType::New(order), &mut order_book_collection);
         //Another 200@100 buy added to book
-        validate_result(&result, 0, 0.0, None);
+        validate_result(&result, 0, px(0.0), None);
 
-        let mut order = Order {
+        let order = Order {
             id_: String::from("3"),
-            price_: 0.0,
+            price_: px(0.0),
             symbol_: String::from("REL"),
-            qty_: 200,
+            qty_: Quantity(200),
             side_: OrderSide::Sell,
             type_: OrderType::Mkt,
-            entry_time_: std::time::SystemTime::now(),
+            ..Default::default()
         };
-        let result = process_event(EventType::New, &mut order, &mut order_book_collection);
+        let result = process_event(EventType::New(order), &mut order_book_collection);
         //mkt matched 200@100
         matched_order_ids.push("1".to_string());
-        validate_result(&result, 200, 100.0, Some(&matched_order_ids));
+        validate_result(&result, 200, px(100.0), Some(&matched_order_ids));
 
-        let mut order = Order {
+        let order = Order {
             id_: String::from("4"),
-            price_: 0.0,
+            price_: px(0.0),
             symbol_: String::from("REL"),
-            qty_: 200,
+            qty_: Quantity(200),
             side_: OrderSide::Sell,
             type_: OrderType::Mkt,
-            entry_time_: std::time::SystemTime::now(),
+            ..Default::default()
         };
-        let result = process_event(EventType::New, &mut order, &mut order_book_collection);
+        let result = process_event(EventType::New(order), &mut order_book_collection);
         //mkt matched 200@100
         matched_order_ids.clear();
         matched_order_ids.push("2".to_string());
-        validate_result(&result, 200, 100.0, Some(&matched_order_ids));
+        validate_result(&result, 200, px(100.0), Some(&matched_order_ids));
     }
 
     #[test]
     fn mkt_order_match_price() {
-        let mut order_book_collection = MatchingEngine {
-            order_book_by_symbol_: HashMap::new(),
-        };
+        let mut order_book_collection = test_engine();
         let mut matched_order_ids = Vec::new();
-        let mut order = Order {
+        let order = Order {
             id_: String::from("1"),
-            price_: 100.0,
+            price_: px(100.0),
             symbol_: String::from("REL"),
-            qty_: 200,
+            qty_: Quantity(200),
             side_: OrderSide::Buy,
             type_: OrderType::Limit,
-            entry_time_: std::time::SystemTime::now(),
+            ..Default::default()
         };
-        let result = process_event(EventType::New, &mut order, &mut order_book_collection);
+        let result = process_event(EventType::New(order), &mut order_book_collection);
         //200@101 buy added to book
-        validate_result(&result, 0, 0.0, None);
+        validate_result(&result, 0, px(0.0), None);
 
-        let mut order = Order {
+        let order = Order {
             id_: String::from("2"),
-            price_: 101.0,
+            price_: px(101.0),
             symbol_: String::from("REL"),
-            qty_: 200,
+            qty_: Quantity(200),
             side_: OrderSide::Buy,
             type_: OrderType::Limit,
-            entry_time_: std::time::SystemTime::now(),
+            ..Default::default()
         };
-        let result = process_event(EventType::New, &mut order, &mut order_book_collection);
+        let result = process_event(EventType::New(order), &mut order_book_collection);
         //Another 200@100 buy added to book
-        validate_result(&result, 0, 0.0, None);
+        validate_result(&result, 0, px(0.0), None);
 
-        let mut order = Order {
+        let order = Order {
             id_: String::from("3"),
-            price_: 0.0,
+            price_: px(0.0),
             symbol_: String::from("REL"),
-            qty_: 200,
+            qty_: Quantity(200),
             side_: OrderSide::Sell,
             type_: OrderType::Mkt,
-            entry_time_: std::time::SystemTime::now(),
+            ..Default::default()
         };
-        let result = process_event(EventType::New, &mut order, &mut order_book_collection);
+        let result = process_event(EventType::New(order), &mut order_book_collection);
         //mkt matched to best price which is 100 at this time
         matched_order_ids.push("2".to_string());
-        validate_result(&result, 200, 101.0, Some(&matched_order_ids));
+        validate_result(&result, 200, px(101.0), Some(&matched_order_ids));
 
-        let mut order = Order {
+        let order = Order {
             id_: String::from("4"),
-            price_: 0.0,
+            price_: px(0.0),
             symbol_: String::from("REL"),
-            qty_: 200,
+            qty_: Quantity(200),
             side_: OrderSide::Sell,
             type_: OrderType::Mkt,
-            entry_time_: std::time::SystemTime::now(),
+            ..Default::default()
         };
-        let result = process_event(EventType::New, &mut order, &mut order_book_collection);
+        let result = process_event(EventType::New(order), &mut order_book_collection);
         //mkt matched
         matched_order_ids.clear();
         matched_order_ids.push("1".to_string());
-        validate_result(&result, 200, 100.0, Some(&matched_order_ids));
+        validate_result(&result, 200, px(100.0), Some(&matched_order_ids));
     }
 
     #[test]
     fn mkt_order_match_price_sell_buy() {
-        let mut order_book_collection = MatchingEngine {
-            order_book_by_symbol_: HashMap::new(),
-        };
+        let mut order_book_collection = test_engine();
         let mut matched_order_ids = Vec::new();
-        let mut order = Order {
+        let order = Order {
             id_: String::from("1"),
-            price_: 102.0,
+            price_: px(102.0),
             symbol_: String::from("REL"),
-            qty_: 200,
+            qty_: Quantity(200),
             side_: OrderSide::Sell,
             type_: OrderType::Limit,
-            entry_time_: std::time::SystemTime::now(),
+            ..Default::default()
         };
-        let result = process_event(EventType::New, &mut order, &mut order_book_collection);
+        let result = process_event(EventType::New(order), &mut order_book_collection);
         //200@101 buy added to book
-        validate_result(&result, 0, 0.0, None);
+        validate_result(&result, 0, px(0.0), None);
 
-        let mut order = Order {
+        let order = Order {
             id_: String::from("2"),
-            price_: 101.0,
+            price_: px(101.0),
             symbol_: String::from("REL"),
-            qty_: 200,
+            qty_: Quantity(200),
             side_: OrderSide::Sell,
             type_: OrderType::Limit,
-            entry_time_: std::time::SystemTime::now(),
+            ..Default::default()
         };
-        let result = process_event(EventType::New, &mut order, &mut order_book_collection);
+        let result = process_event(EventType::New(order), &mut order_book_collection);
         //Another 200@100 buy added to book
-        validate_result(&result, 0, 0.0, None);
+        validate_result(&result, 0, px(0.0), None);
 
-        let mut order = Order {
+        let order = Order {
             id_: String::from("3"),
-            price_: 0.0,
+            price_: px(0.0),
             symbol_: String::from("REL"),
-            qty_: 200,
+            qty_: Quantity(200),
             side_: OrderSide::Buy,
             type_: OrderType::Mkt,
-            entry_time_: std::time::SystemTime::now(),
+            ..Default::default()
         };
-        let result = process_event(EventType::New, &mut order, &mut order_book_collection);
+        let result = process_event(EventType::New(order), &mut order_book_collection);
         //mkt matched to best price which is 100 at this time
         matched_order_ids.push("2".to_string());
-        validate_result(&result, 200, 101.0, Some(&matched_order_ids));
+        validate_result(&result, 200, px(101.0), Some(&matched_order_ids));
 
-        let mut order = Order {
+        let order = Order {
             id_: String::from("4"),
-            price_: 0.0,
+            price_: px(0.0),
             symbol_: String::from("REL"),
-            qty_: 200,
+            qty_: Quantity(200),
             side_: OrderSide::Buy,
             type_: OrderType::Mkt,
-            entry_time_: std::time::SystemTime::now(),
+            ..Default::default()
         };
-        let result = process_event(EventType::New, &mut order, &mut order_book_collection);
+        let result = process_event(EventType::New(order), &mut order_book_collection);
         //mkt matched
         matched_order_ids.clear();
         matched_order_ids.push("1".to_string());
-        validate_result(&result, 200, 102.0, Some(&matched_order_ids));
+        validate_result(&result, 200, px(102.0), Some(&matched_order_ids));
     }
 
     #[test]
     fn cancel_order_simple() {
-        let mut order_book_collection = MatchingEngine {
-            order_book_by_symbol_: HashMap::new(),
-        };
+        let mut order_book_collection = test_engine();
 
         //New order
-        let mut order = Order {
+        let order = Order {
             id_: String::from("1"),
-            price_: 100.1,
+            price_: px(100.1),
             symbol_: String::from("REL"),
-            qty_: 200,
+            qty_: Quantity(200),
             side_: OrderSide::Buy,
             type_: OrderType::Limit,
-            entry_time_: std::time::SystemTime::now(),
+            ..Default::default()
         };
-        let result = process_event(EventType::New, &mut order, &mut order_book_collection);
+        let result = process_event(EventType::New(order), &mut order_book_collection);
         //mkt matched to best price which is 100 at this time
         let mut matched_order_ids = Vec::new();
-        validate_result(&result, 00, 0.0, Some(&matched_order_ids));
+        validate_result(&result, 00, px(0.0), Some(&matched_order_ids));
 
         //Cancel order id 1 , execqty 0 no erro
-        let mut order = Order {
+        let cancel_request = CancelRequest {
             id_: String::from("1"),
-            price_: 100.1,
-            symbol_: String::from("REL"),
-            qty_: 200,
-            side_: OrderSide::Buy,
-            type_: OrderType::Limit,
-            entry_time_: std::time::SystemTime::now(),
         };
 
-        let result = process_event(EventType::Cxl, &mut order, &mut order_book_collection);
+        let result = process_event(EventType::Cxl(cancel_request), &mut order_book_collection);
         //mkt matched to best price which is 100 at this time
         matched_order_ids.clear();
-        validate_result(&result, 00, 0.0, Some(&matched_order_ids));
+        validate_result(&result, 00, px(0.0), Some(&matched_order_ids));
 
         //Sending matching order to 1, but it should have been removed so no exec qty
-        let mut order = Order {
+        let order = Order {
             id_: String::from("2"),
-            price_: 100.1,
+            price_: px(100.1),
             symbol_: String::from("REL"),
-            qty_: 200,
+            qty_: Quantity(200),
             side_: OrderSide::Sell,
             type_: OrderType::Limit,
-            entry_time_: std::time::SystemTime::now(),
+            ..Default::default()
         };
 
-        let result = process_event(EventType::New, &mut order, &mut order_book_collection);
+        let result = process_event(EventType::New(order), &mut order_book_collection);
         //mkt matched to best price which is 100 at this time
         matched_order_ids.clear();
-        validate_result(&result, 00, 0.0, Some(&matched_order_ids));
+        validate_result(&result, 00, px(0.0), Some(&matched_order_ids));
 
         //Sending matching order to 2, it should get executed
-        let mut order = Order {
+        let order = Order {
             id_: String::from("3"),
-            price_: 100.1,
+            price_: px(100.1),
             symbol_: String::from("REL"),
-            qty_: 200,
+            qty_: Quantity(200),
             side_: OrderSide::Buy,
             type_: OrderType::Limit,
-            entry_time_: std::time::SystemTime::now(),
+            ..Default::default()
         };
 
-        let result = process_event(EventType::New, &mut order, &mut order_book_collection);
+        let result = process_event(EventType::New(order), &mut order_book_collection);
         //mkt matched to best price which is 100 at this time
         matched_order_ids.push(String::from("2"));
-        validate_result(&result, 200, 100.1, Some(&matched_order_ids));
+        validate_result(&result, 200, px(100.1), Some(&matched_order_ids));
     }
 
     #[test]
     fn simple_replace_order() {
-        let mut order_book_collection = MatchingEngine {
-            order_book_by_symbol_: HashMap::new(),
-        };
+        let mut order_book_collection = test_engine();
 
         //New order
-        let mut order = Order {
+        let order = Order {
             id_: String::from("1"),
-            price_: 100.1,
+            price_: px(100.1),
             symbol_: String::from("REL"),
-            qty_: 200,
+            qty_: Quantity(200),
             side_: OrderSide::Buy,
             type_: OrderType::Limit,
-            entry_time_: std::time::SystemTime::now(),
+            ..Default::default()
         };
-        let result = process_event(EventType::New, &mut order, &mut order_book_collection);
+        let result = process_event(EventType::New(order), &mut order_book_collection);
         //mkt matched to best price which is 100 at this time
         let mut matched_order_ids = Vec::new();
-        validate_result(&result, 00, 0.0, Some(&matched_order_ids));
+        validate_result(&result, 00, px(0.0), Some(&matched_order_ids));
 
         //Replace order id 1, make price less aggressive
-        let mut order = Order {
+        let replace_request = ReplaceRequest {
             id_: String::from("1"),
-            price_: 100.0,
             symbol_: String::from("REL"),
-            qty_: 200,
             side_: OrderSide::Buy,
-            type_: OrderType::Limit,
-            entry_time_: std::time::SystemTime::now(),
+            qty_: Quantity(200),
+            price_: px(100.0),
         };
-        let result = process_event(EventType::Rpl, &mut order, &mut order_book_collection);
+        let result = process_event(EventType::Rpl(replace_request), &mut order_book_collection);
         //mkt matched to best price which is 100 at this time
         matched_order_ids.clear();
-        validate_result(&result, 00, 0.0, Some(&matched_order_ids));
+        validate_result(&result, 00, px(0.0), Some(&matched_order_ids));
 
         //sending sell order with less aggressive price so it does not match
-        let mut order = Order {
+        let order = Order {
             id_: String::from("2"),
-            price_: 100.1,
+            price_: px(100.1),
             symbol_: String::from("REL"),
-            qty_: 200,
+            qty_: Quantity(200),
             side_: OrderSide::Sell,
             type_: OrderType::Limit,
-            entry_time_: std::time::SystemTime::now(),
+            ..Default::default()
         };
-        let result = process_event(EventType::New, &mut order, &mut order_book_collection);
+        let result = process_event(EventType::New(order), &mut order_book_collection);
         //mkt matched to best price which is 100 at this time
         matched_order_ids.clear();
-        validate_result(&result, 00, 0.0, Some(&matched_order_ids));
+        validate_result(&result, 00, px(0.0), Some(&matched_order_ids));
 
         //Replace above sell order with more aggressive price so it does match
-        let mut order = Order {
+        let replace_request = ReplaceRequest {
+            id_: String::from("2"),
+            symbol_: String::from("REL"),
+            side_: OrderSide::Sell,
+            qty_: Quantity(200),
+            price_: px(100.0),
+        };
+        let result = process_event(EventType::Rpl(replace_request), &mut order_book_collection);
+
+        //mkt matched to best price which is 100 at this time
+        matched_order_ids.push(String::from("1"));
+        validate_result(&result, 200, px(100.0), Some(&matched_order_ids));
+    }
+
+    #[test]
+    fn limit_order_sweeps_crossing_levels() {
+        let mut order_book_collection = test_engine();
+
+        for (id, price) in [("1", 100.0), ("2", 101.0), ("3", 102.0)] {
+            let order = Order {
+                id_: String::from(id),
+                price_: px(price),
+                symbol_: String::from("REL"),
+                qty_: Quantity(100),
+                side_: OrderSide::Sell,
+                type_: OrderType::Limit,
+                ..Default::default()
+            };
+            let result = process_event(EventType::New(order), &mut order_book_collection);
+            validate_result(&result, 0, px(0.0), None);
+        }
+
+        //buy 300@101 takes 100@100 and 100@101, remaining 100 rests at 101
+        let order = Order {
+            id_: String::from("4"),
+            price_: px(101.0),
+            symbol_: String::from("REL"),
+            qty_: Quantity(300),
+            side_: OrderSide::Buy,
+            type_: OrderType::Limit,
+            ..Default::default()
+        };
+        let result = process_event(EventType::New(order), &mut order_book_collection);
+        let matched_order_ids = vec![String::from("1"), String::from("2")];
+        validate_result(&result, 200, px(100.5), Some(&matched_order_ids));
+        let fills = result.unwrap().fills().clone();
+        assert_eq!(fills.last().unwrap().aggressor_leaves_qty_, Quantity(100));
+
+        //book is not crossed, the remainder is the best bid
+        let order_book = order_book_collection
+            .get_book_by_symbol(&String::from("REL"))
+            .unwrap();
+        assert_eq!(
+            order_book.best_level(OrderSide::Buy).unwrap().price_,
+            px(101.0)
+        );
+        assert_eq!(
+            order_book.best_level(OrderSide::Sell).unwrap().price_,
+            px(102.0)
+        );
+
+        let order = Order {
+            id_: String::from("5"),
+            price_: px(100.0),
+            symbol_: String::from("REL"),
+            qty_: Quantity(100),
+            side_: OrderSide::Sell,
+            type_: OrderType::Limit,
+            ..Default::default()
+        };
+        let result = process_event(EventType::New(order), &mut order_book_collection);
+        //sell 100@100 executes at the resting bid price 101
+        let matched_order_ids = vec![String::from("4")];
+        validate_result(&result, 100, px(101.0), Some(&matched_order_ids));
+    }
+
+    #[test]
+    fn replace_order_sweeps_crossing_levels() {
+        let mut order_book_collection = test_engine();
+
+        let order = Order {
+            id_: String::from("1"),
+            price_: px(100.0),
+            symbol_: String::from("REL"),
+            qty_: Quantity(200),
+            side_: OrderSide::Buy,
+            type_: OrderType::Limit,
+            ..Default::default()
+        };
+        let result = process_event(EventType::New(order), &mut order_book_collection);
+        validate_result(&result, 0, px(0.0), None);
+
+        for (id, price) in [("2", 101.0), ("3", 102.0)] {
+            let order = Order {
+                id_: String::from(id),
+                price_: px(price),
+                symbol_: String::from("REL"),
+                qty_: Quantity(100),
+                side_: OrderSide::Sell,
+                type_: OrderType::Limit,
+                ..Default::default()
+            };
+            let result = process_event(EventType::New(order), &mut order_book_collection);
+            validate_result(&result, 0, px(0.0), None);
+        }
+
+        //Replace buy to 102, it takes both ask levels
+        let replace_request = ReplaceRequest {
+            id_: String::from("1"),
+            symbol_: String::from("REL"),
+            side_: OrderSide::Buy,
+            qty_: Quantity(200),
+            price_: px(102.0),
+        };
+        let result = process_event(EventType::Rpl(replace_request), &mut order_book_collection);
+        let matched_order_ids = vec![String::from("2"), String::from("3")];
+        validate_result(&result, 200, px(101.5), Some(&matched_order_ids));
+
+        let order_book = order_book_collection
+            .get_book_by_symbol(&String::from("REL"))
+            .unwrap();
+        assert!(order_book.bids_.is_empty());
+        assert!(order_book.asks_.is_empty());
+    }
+
+    #[test]
+    fn mkt_order_sweeps_levels_and_cancels_remaining() {
+        let mut order_book_collection = test_engine();
+
+        //First order is mkt, nothing to match so it is cancelled
+        let order = Order {
+            id_: String::from("1"),
+            symbol_: String::from("REL"),
+            qty_: Quantity(100),
+            side_: OrderSide::Buy,
+            type_: OrderType::Mkt,
+            ..Default::default()
+        };
+        let result = process_event(EventType::New(order), &mut order_book_collection);
+        validate_result(&result, 0, px(0.0), None);
+        assert_eq!(result.unwrap().cancelled_qty_, Quantity(100));
+
+        for (id, price) in [("2", 100.0), ("3", 101.0)] {
+            let order = Order {
+                id_: String::from(id),
+                price_: px(price),
+                symbol_: String::from("REL"),
+                qty_: Quantity(100),
+                side_: OrderSide::Sell,
+                type_: OrderType::Limit,
+                ..Default::default()
+            };
+            let result = process_event(EventType::New(order), &mut order_book_collection);
+            validate_result(&result, 0, px(0.0), None);
+        }
+
+        //mkt buy 300 takes both levels, remaining 100 is cancelled
+        let order = Order {
+            id_: String::from("4"),
+            symbol_: String::from("REL"),
+            qty_: Quantity(300),
+            side_: OrderSide::Buy,
+            type_: OrderType::Mkt,
+            ..Default::default()
+        };
+        let result = process_event(EventType::New(order), &mut order_book_collection);
+        let matched_order_ids = vec![String::from("2"), String::from("3")];
+        validate_result(&result, 200, px(100.5), Some(&matched_order_ids));
+        assert_eq!(result.unwrap().cancelled_qty_, Quantity(100));
+
+        let order_book = order_book_collection
+            .get_book_by_symbol(&String::from("REL"))
+            .unwrap();
+        assert!(order_book.bids_.is_empty());
+        assert!(order_book.asks_.is_empty());
+    }
+
+    #[test]
+    fn mkt_order_with_protection_price() {
+        let mut order_book_collection = test_engine();
+
+        for (id, price) in [("1", 100.0), ("2", 99.0), ("3", 98.0)] {
+            let order = Order {
+                id_: String::from(id),
+                price_: px(price),
+                symbol_: String::from("REL"),
+                qty_: Quantity(100),
+                side_: OrderSide::Buy,
+                type_: OrderType::Limit,
+                ..Default::default()
+            };
+            let result = process_event(EventType::New(order), &mut order_book_collection);
+            validate_result(&result, 0, px(0.0), None);
+        }
+
+        //mkt sell 300 protected at 99 does not reach the 98 bid
+        let order = Order {
+            id_: String::from("4"),
+            symbol_: String::from("REL"),
+            qty_: Quantity(300),
+            side_: OrderSide::Sell,
+            type_: OrderType::Mkt,
+            protection_price_: Some(px(99.0)),
+            ..Default::default()
+        };
+        let result = process_event(EventType::New(order), &mut order_book_collection);
+        let matched_order_ids = vec![String::from("1"), String::from("2")];
+        validate_result(&result, 200, px(99.5), Some(&matched_order_ids));
+        assert_eq!(result.unwrap().cancelled_qty_, Quantity(100));
+
+        let order_book = order_book_collection
+            .get_book_by_symbol(&String::from("REL"))
+            .unwrap();
+        assert!(order_book.asks_.is_empty());
+        assert_eq!(
+            order_book.best_level(OrderSide::Buy).unwrap().price_,
+            px(98.0)
+        );
+    }
+
+    #[test]
+    fn fill_per_contra_order() {
+        let mut order_book_collection = test_engine();
+
+        for (id, qty) in [("1", 100), ("2", 200)] {
+            let order = Order {
+                id_: String::from(id),
+                price_: px(100.0),
+                symbol_: String::from("REL"),
+                qty_: Quantity(qty),
+                side_: OrderSide::Sell,
+                type_: OrderType::Limit,
+                ..Default::default()
+            };
+            let result = process_event(EventType::New(order), &mut order_book_collection);
+            validate_result(&result, 0, px(0.0), None);
+        }
+
+        let order = Order {
+            id_: String::from("3"),
+            price_: px(100.0),
+            symbol_: String::from("REL"),
+            qty_: Quantity(250),
+            side_: OrderSide::Buy,
+            type_: OrderType::Limit,
+            ..Default::default()
+        };
+        let result = process_event(EventType::New(order), &mut order_book_collection);
+        let expected_fills = vec![
+            Fill {
+                trade_id_: 1,
+                seq_: EventSeq {
+                    seq_num_: 4,
+                    symbol_seq_num_: 4,
+                },
+                aggressor_id_: String::from("3"),
+                resting_id_: String::from("1"),
+                qty_: Quantity(100),
+                price_: px(100.0),
+                aggressor_leaves_qty_: Quantity(150),
+                resting_leaves_qty_: Quantity(0),
+            },
+            Fill {
+                trade_id_: 2,
+                seq_: EventSeq {
+                    seq_num_: 5,
+                    symbol_seq_num_: 5,
+                },
+                aggressor_id_: String::from("3"),
+                resting_id_: String::from("2"),
+                qty_: Quantity(150),
+                price_: px(100.0),
+                aggressor_leaves_qty_: Quantity(0),
+                resting_leaves_qty_: Quantity(50),
+            },
+        ];
+        assert_eq!(result.unwrap().fills(), &expected_fills);
+
+        let order = Order {
+            id_: String::from("4"),
+            price_: px(100.0),
+            symbol_: String::from("REL"),
+            qty_: Quantity(50),
+            side_: OrderSide::Buy,
+            type_: OrderType::Limit,
+            ..Default::default()
+        };
+        let result = process_event(EventType::New(order), &mut order_book_collection);
+        let expected_fills = vec![Fill {
+            trade_id_: 3,
+            seq_: EventSeq {
+                seq_num_: 7,
+                symbol_seq_num_: 7,
+            },
+            aggressor_id_: String::from("4"),
+            resting_id_: String::from("2"),
+            qty_: Quantity(50),
+            price_: px(100.0),
+            aggressor_leaves_qty_: Quantity(0),
+            resting_leaves_qty_: Quantity(0),
+        }];
+        assert_eq!(result.unwrap().fills(), &expected_fills);
+    }
+
+    #[test]
+    fn same_entry_time_keeps_every_order() {
+        //the clock does not move, every order is stamped with the same entry time
+        let clock = Arc::new(ManualClock::new(1_000));
+        let mut order_book_collection =
+            MatchingEngine::with_clock(test_registry(vec![test_instrument("REL")]), clock);
+
+        for id in ["1", "2", "3"] {
+            let order = Order {
+                id_: String::from(id),
+                price_: px(100.0),
+                symbol_: String::from("REL"),
+                qty_: Quantity(100),
+                side_: OrderSide::Buy,
+                type_: OrderType::Limit,
+                ..Default::default()
+            };
+            let result = process_event(EventType::New(order), &mut order_book_collection);
+            validate_result(&result, 0, px(0.0), None);
+        }
+
+        let order_book = order_book_collection
+            .get_book_by_symbol(&String::from("REL"))
+            .unwrap();
+        assert_eq!(
+            order_book.best_level(OrderSide::Buy).unwrap().orders_.len(),
+            3
+        );
+        assert!(order_book
+            .best_level(OrderSide::Buy)
+            .unwrap()
+            .orders_
+            .values()
+            .all(|order| order.entry_time_ == 1_000));
+
+        //cancel the middle one, the others keep their priority
+        let cancel_request = CancelRequest {
             id_: String::from("2"),
-            price_: 100.0,
+        };
+        let result = process_event(EventType::Cxl(cancel_request), &mut order_book_collection);
+        validate_result(&result, 0, px(0.0), None);
+
+        //matched in arrival order
+        let order = Order {
+            id_: String::from("4"),
+            price_: px(100.0),
             symbol_: String::from("REL"),
-            qty_: 200,
+            qty_: Quantity(200),
             side_: OrderSide::Sell,
             type_: OrderType::Limit,
-            entry_time_: std::time::SystemTime::now(),
+            ..Default::default()
+        };
+        let result = process_event(EventType::New(order), &mut order_book_collection);
+        let matched_order_ids = vec![String::from("1"), String::from("3")];
+        validate_result(&result, 200, px(100.0), Some(&matched_order_ids));
+    }
+
+    #[test]
+    fn order_index_in_sync() {
+        let mut order_book_collection = test_engine();
+
+        for (id, price) in [("1", 100.0), ("2", 100.0), ("3", 101.0)] {
+            let order = Order {
+                id_: String::from(id),
+                price_: px(price),
+                symbol_: String::from("REL"),
+                qty_: Quantity(100),
+                side_: OrderSide::Sell,
+                type_: OrderType::Limit,
+                ..Default::default()
+            };
+            let result = process_event(EventType::New(order), &mut order_book_collection);
+            validate_result(&result, 0, px(0.0), None);
+        }
+
+        let order_book = order_book_collection
+            .get_book_by_symbol(&String::from("REL"))
+            .unwrap();
+        assert_eq!(order_book.order_index_.len(), 3);
+        let location = order_book.order_index_[&String::from("3")];
+        assert_eq!(location.side_, OrderSide::Sell);
+        assert_eq!(location.price_, px(101.0));
+
+        //1 is fully filled and 2 is partially filled
+        let order = Order {
+            id_: String::from("4"),
+            price_: px(100.0),
+            symbol_: String::from("REL"),
+            qty_: Quantity(150),
+            side_: OrderSide::Buy,
+            type_: OrderType::Limit,
+            ..Default::default()
+        };
+        let result = process_event(EventType::New(order), &mut order_book_collection);
+        let matched_order_ids = vec![String::from("1"), String::from("2")];
+        validate_result(&result, 150, px(100.0), Some(&matched_order_ids));
+
+        let order_book = order_book_collection
+            .get_book_by_symbol(&String::from("REL"))
+            .unwrap();
+        assert!(!order_book.order_index_.contains_key("1"));
+        assert!(order_book.order_index_.contains_key("2"));
+
+        //cancel the last order of a level removes the level too
+        let cancel_request = CancelRequest {
+            id_: String::from("3"),
+        };
+        let result = process_event(EventType::Cxl(cancel_request), &mut order_book_collection);
+        validate_result(&result, 0, px(0.0), None);
+
+        //cancel of an order which is not in the book anymore fails
+        let cancel_request = CancelRequest {
+            id_: String::from("1"),
+        };
+        let result = process_event(EventType::Cxl(cancel_request), &mut order_book_collection);
+        assert!(result.is_err());
+
+        let order_book = order_book_collection
+            .get_book_by_symbol(&String::from("REL"))
+            .unwrap();
+        assert_eq!(order_book.order_index_.len(), 1);
+        assert_eq!(order_book.asks_.len(), 1);
+        assert!(order_book.bids_.is_empty());
+    }
+
+    #[test]
+    fn live_order_id_cannot_be_reused() {
+        let mut order_book_collection = test_engine();
+        let order = |id: &str, symbol: &str, type_: OrderType| Order {
+            id_: String::from(id),
+            price_: px(100.0),
+            symbol_: String::from(symbol),
+            qty_: Quantity(100),
+            side_: OrderSide::Buy,
+            type_,
+            stop_price_: px(105.0),
+            ..Default::default()
         };
+
         let result = process_event(
-          EventType::Rpl, 
-          &mut order, 
-          &mut order_book_collection
+            EventType::New(order("1", "REL", OrderType::Limit)),
+            &mut order_book_collection,
         );
-        
-        //mkt matched to best price which is 100 at this time
-        matched_order_ids.push(String::from("1"));
-        validate_result(&result, 200, 100.0, Some(&matched_order_ids));
+        validate_result(&result, 0, px(0.0), None);
+        let result = process_event(
+            EventType::New(order("2", "REL", OrderType::Stop)),
+            &mut order_book_collection,
+        );
+        validate_result(&result, 0, px(0.0), None);
+
+        //resting id, resting id in another book and waiting stop id
+        for (id, symbol) in [("1", "REL"), ("1", "TCS"), ("2", "REL")] {
+            let result = process_event(
+                EventType::New(order(id, symbol, OrderType::Limit)),
+                &mut order_book_collection,
+            );
+            assert_eq!(
+                result.unwrap_err().reason_,
+                EngineError::DuplicateOrderId {
+                    order_id_: String::from(id)
+                }
+            );
+        }
+        let order_book = order_book_collection
+            .get_book_by_symbol(&String::from("REL"))
+            .unwrap();
+        assert_eq!(order_book.order_index_.len(), 1);
+        assert_eq!(order_book.bids_[&px(100.0)].orders_.len(), 1);
+        assert_eq!(order_book.stop_book_.orders().count(), 1);
+        assert!(order_book_collection
+            .get_book_by_symbol(&String::from("TCS"))
+            .is_none_or(|order_book| order_book.order_index_.is_empty()));
+
+        //id is free again once the order is gone
+        let result = process_event(
+            EventType::Cxl(CancelRequest {
+                id_: String::from("1"),
+            }),
+            &mut order_book_collection,
+        );
+        validate_result(&result, 0, px(0.0), None);
+        let result = process_event(
+            EventType::New(order("1", "TCS", OrderType::Limit)),
+            &mut order_book_collection,
+        );
+        validate_result(&result, 0, px(0.0), None);
+    }
+
+    #[test]
+    fn cancel_replace_by_order_id() {
+        let mut order_book_collection = test_engine();
+
+        for (id, symbol, side) in [("1", "REL", OrderSide::Buy), ("2", "TCS", OrderSide::Sell)] {
+            let order = Order {
+                id_: String::from(id),
+                price_: px(100.0),
+                symbol_: String::from(symbol),
+                qty_: Quantity(100),
+                side_: side,
+                type_: OrderType::Limit,
+                ..Default::default()
+            };
+            let result = process_event(EventType::New(order), &mut order_book_collection);
+            validate_result(&result, 0, px(0.0), None);
+        }
+
+        //symbol and side can not be changed
+        let replace_request = ReplaceRequest {
+            id_: String::from("1"),
+            symbol_: String::from("TCS"),
+            side_: OrderSide::Buy,
+            qty_: Quantity(100),
+            price_: px(101.0),
+        };
+        let result = process_event(EventType::Rpl(replace_request), &mut order_book_collection);
+        assert!(result.is_err());
+
+        let replace_request = ReplaceRequest {
+            id_: String::from("1"),
+            symbol_: String::from("REL"),
+            side_: OrderSide::Sell,
+            qty_: Quantity(100),
+            price_: px(101.0),
+        };
+        let result = process_event(EventType::Rpl(replace_request), &mut order_book_collection);
+        assert!(result.is_err());
+
+        let replace_request = ReplaceRequest {
+            id_: String::from("3"),
+            symbol_: String::from("REL"),
+            side_: OrderSide::Buy,
+            qty_: Quantity(100),
+            price_: px(101.0),
+        };
+        let result = process_event(EventType::Rpl(replace_request), &mut order_book_collection);
+        assert!(result.is_err());
+
+        //failed replace leaves the original untouched
+        let order_book = order_book_collection
+            .get_book_by_symbol(&String::from("REL"))
+            .unwrap();
+        let original_order = order_book.get_order(&String::from("1")).unwrap();
+        assert_eq!(original_order.price_, px(100.0));
+        assert_eq!(original_order.qty_, Quantity(100));
+
+        let replace_request = ReplaceRequest {
+            id_: String::from("1"),
+            symbol_: String::from("REL"),
+            side_: OrderSide::Buy,
+            qty_: Quantity(50),
+            price_: px(101.0),
+        };
+        let result = process_event(EventType::Rpl(replace_request), &mut order_book_collection);
+        validate_result(&result, 0, px(0.0), None);
+
+        let order_book = order_book_collection
+            .get_book_by_symbol(&String::from("REL"))
+            .unwrap();
+        let replaced_order = order_book.get_order(&String::from("1")).unwrap();
+        assert_eq!(replaced_order.price_, px(101.0));
+        assert_eq!(replaced_order.qty_, Quantity(50));
+
+        //cancel only needs the order id, symbol and side are resolved by the engine
+        let cancel_request = CancelRequest {
+            id_: String::from("2"),
+        };
+        let result = process_event(EventType::Cxl(cancel_request), &mut order_book_collection);
+        validate_result(&result, 0, px(0.0), None);
+
+        let cancel_request = CancelRequest {
+            id_: String::from("2"),
+        };
+        let result = process_event(EventType::Cxl(cancel_request), &mut order_book_collection);
+        assert!(result.is_err());
+
+        let order_book = order_book_collection
+            .get_book_by_symbol(&String::from("TCS"))
+            .unwrap();
+        assert!(order_book.asks_.is_empty());
+    }
+    fn queue_order_ids(
+        p_engine: &mut MatchingEngine,
+        p_side: OrderSide,
+        p_price: Price,
+    ) -> Vec<String> {
+        let order_book = p_engine.get_book_by_symbol(&String::from("REL")).unwrap();
+        order_book
+            .levels_mut(p_side)
+            .get(&p_price)
+            .map(|level| {
+                level
+                    .orders_
+                    .values()
+                    .map(|order| order.id_.to_owned())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn amend_keeps_priority_for_qty_reduction() {
+        let mut order_book_collection = test_engine();
+
+        for id in ["1", "2", "3"] {
+            let order = Order {
+                id_: String::from(id),
+                price_: px(100.0),
+                symbol_: String::from("REL"),
+                qty_: Quantity(100),
+                side_: OrderSide::Buy,
+                type_: OrderType::Limit,
+                ..Default::default()
+            };
+            let result = process_event(EventType::New(order), &mut order_book_collection);
+            validate_result(&result, 0, px(0.0), None);
+        }
+
+        //qty reduction at the same price stays in front of the queue
+        let replace_request = ReplaceRequest {
+            id_: String::from("1"),
+            symbol_: String::from("REL"),
+            side_: OrderSide::Buy,
+            qty_: Quantity(50),
+            price_: px(100.0),
+        };
+        let result = process_event(EventType::Rpl(replace_request), &mut order_book_collection);
+        validate_result(&result, 0, px(0.0), None);
+        assert_eq!(
+            queue_order_ids(&mut order_book_collection, OrderSide::Buy, px(100.0)),
+            vec!["1", "2", "3"]
+        );
+
+        //qty increase goes to the back of the queue
+        let replace_request = ReplaceRequest {
+            id_: String::from("2"),
+            symbol_: String::from("REL"),
+            side_: OrderSide::Buy,
+            qty_: Quantity(200),
+            price_: px(100.0),
+        };
+        let result = process_event(EventType::Rpl(replace_request), &mut order_book_collection);
+        validate_result(&result, 0, px(0.0), None);
+        assert_eq!(
+            queue_order_ids(&mut order_book_collection, OrderSide::Buy, px(100.0)),
+            vec!["1", "3", "2"]
+        );
+
+        //price change loses priority even when it comes back to the same price
+        let replace_request = ReplaceRequest {
+            id_: String::from("1"),
+            symbol_: String::from("REL"),
+            side_: OrderSide::Buy,
+            qty_: Quantity(50),
+            price_: px(99.0),
+        };
+        let result = process_event(EventType::Rpl(replace_request), &mut order_book_collection);
+        validate_result(&result, 0, px(0.0), None);
+        let replace_request = ReplaceRequest {
+            id_: String::from("1"),
+            symbol_: String::from("REL"),
+            side_: OrderSide::Buy,
+            qty_: Quantity(50),
+            price_: px(100.0),
+        };
+        let result = process_event(EventType::Rpl(replace_request), &mut order_book_collection);
+        validate_result(&result, 0, px(0.0), None);
+        assert_eq!(
+            queue_order_ids(&mut order_book_collection, OrderSide::Buy, px(100.0)),
+            vec!["3", "2", "1"]
+        );
+
+        let order = Order {
+            id_: String::from("4"),
+            price_: px(100.0),
+            symbol_: String::from("REL"),
+            qty_: Quantity(150),
+            side_: OrderSide::Sell,
+            type_: OrderType::Limit,
+            ..Default::default()
+        };
+        let result = process_event(EventType::New(order), &mut order_book_collection);
+        let matched_order_ids = vec![String::from("3"), String::from("2")];
+        validate_result(&result, 150, px(100.0), Some(&matched_order_ids));
+        assert_eq!(
+            queue_order_ids(&mut order_book_collection, OrderSide::Buy, px(100.0)),
+            vec!["2", "1"]
+        );
+    }
+
+    #[test]
+    fn ioc_order_cancels_remaining() {
+        let mut order_book_collection = test_engine();
+
+        let order = Order {
+            id_: String::from("1"),
+            price_: px(100.0),
+            symbol_: String::from("REL"),
+            qty_: Quantity(100),
+            side_: OrderSide::Sell,
+            type_: OrderType::Limit,
+            ..Default::default()
+        };
+        let result = process_event(EventType::New(order), &mut order_book_collection);
+        validate_result(&result, 0, px(0.0), None);
+
+        //IOC takes what is available and the rest is cancelled
+        let order = Order {
+            id_: String::from("2"),
+            price_: px(100.0),
+            symbol_: String::from("REL"),
+            qty_: Quantity(300),
+            side_: OrderSide::Buy,
+            type_: OrderType::Limit,
+            time_in_force_: TimeInForce::Ioc,
+            ..Default::default()
+        };
+        let result = process_event(EventType::New(order), &mut order_book_collection);
+        let matched_order_ids = vec![String::from("1")];
+        validate_result(&result, 100, px(100.0), Some(&matched_order_ids));
+        assert_eq!(result.unwrap().cancelled_qty_, Quantity(200));
+
+        let order_book = order_book_collection
+            .get_book_by_symbol(&String::from("REL"))
+            .unwrap();
+        assert!(order_book.bids_.is_empty());
+        assert!(order_book.asks_.is_empty());
+        assert!(order_book.order_index_.is_empty());
+    }
+
+    #[test]
+    fn fok_order_all_or_nothing() {
+        let mut order_book_collection = test_engine();
+
+        for (id, price) in [("1", 100.0), ("2", 101.0)] {
+            let order = Order {
+                id_: String::from(id),
+                price_: px(price),
+                symbol_: String::from("REL"),
+                qty_: Quantity(100),
+                side_: OrderSide::Sell,
+                type_: OrderType::Limit,
+                ..Default::default()
+            };
+            let result = process_event(EventType::New(order), &mut order_book_collection);
+            validate_result(&result, 0, px(0.0), None);
+        }
+
+        //only 100 is available at 100, FOK is cancelled without any fill
+        let order = Order {
+            id_: String::from("3"),
+            price_: px(100.0),
+            symbol_: String::from("REL"),
+            qty_: Quantity(150),
+            side_: OrderSide::Buy,
+            type_: OrderType::Limit,
+            time_in_force_: TimeInForce::Fok,
+            ..Default::default()
+        };
+        let result = process_event(EventType::New(order), &mut order_book_collection);
+        validate_result(&result, 0, px(0.0), None);
+        assert_eq!(result.unwrap().cancelled_qty_, Quantity(150));
+        assert_eq!(
+            queue_order_ids(&mut order_book_collection, OrderSide::Sell, px(100.0)),
+            vec!["1"]
+        );
+
+        //200 is available up to 101, FOK is fully filled
+        let order = Order {
+            id_: String::from("4"),
+            price_: px(101.0),
+            symbol_: String::from("REL"),
+            qty_: Quantity(200),
+            side_: OrderSide::Buy,
+            type_: OrderType::Limit,
+            time_in_force_: TimeInForce::Fok,
+            ..Default::default()
+        };
+        let result = process_event(EventType::New(order), &mut order_book_collection);
+        let matched_order_ids = vec![String::from("1"), String::from("2")];
+        validate_result(&result, 200, px(100.5), Some(&matched_order_ids));
+        assert_eq!(result.unwrap().cancelled_qty_, Quantity(0));
+    }
+
+    #[test]
+    fn fok_order_does_not_count_on_own_client_qty() {
+        let mut order_book_collection = test_engine();
+
+        for (id, client_id) in [("1", "A"), ("2", "B")] {
+            let order = Order {
+                id_: String::from(id),
+                price_: px(100.0),
+                symbol_: String::from("REL"),
+                qty_: Quantity(50),
+                side_: OrderSide::Sell,
+                type_: OrderType::Limit,
+                client_id_: Some(String::from(client_id)),
+                ..Default::default()
+            };
+            let result = process_event(EventType::New(order), &mut order_book_collection);
+            validate_result(&result, 0, px(0.0), None);
+        }
+
+        //self-match prevention would leave 50 unfilled, FOK is cancelled without any fill
+        let order = Order {
+            id_: String::from("3"),
+            price_: px(100.0),
+            symbol_: String::from("REL"),
+            qty_: Quantity(100),
+            side_: OrderSide::Buy,
+            type_: OrderType::Limit,
+            time_in_force_: TimeInForce::Fok,
+            client_id_: Some(String::from("A")),
+            ..Default::default()
+        };
+        let result = process_event(EventType::New(order), &mut order_book_collection);
+        validate_result(&result, 0, px(0.0), None);
+        let match_result = result.unwrap();
+        assert_eq!(match_result.cancelled_qty_, Quantity(100));
+        assert!(match_result.self_match_reports().is_empty());
+        assert_eq!(
+            queue_order_ids(&mut order_book_collection, OrderSide::Sell, px(100.0)),
+            vec!["1", "2"]
+        );
+    }
+
+    #[test]
+    fn day_and_gtd_orders_expire() {
+        let mut order_book_collection = test_engine();
+        let now = std::time::SystemTime::now();
+        let one_hour = std::time::Duration::from_secs(3600);
+
+        for (id, time_in_force) in [
+            ("1", TimeInForce::Day),
+            ("2", TimeInForce::Gtc),
+            ("3", TimeInForce::Gtd(now + one_hour)),
+            ("4", TimeInForce::Gtd(now + one_hour * 2)),
+        ] {
+            let order = Order {
+                id_: String::from(id),
+                price_: px(100.0),
+                symbol_: String::from("REL"),
+                qty_: Quantity(100),
+                side_: OrderSide::Buy,
+                type_: OrderType::Limit,
+                time_in_force_: time_in_force,
+                ..Default::default()
+            };
+            let result = process_event(EventType::New(order), &mut order_book_collection);
+            validate_result(&result, 0, px(0.0), None);
+        }
+
+        assert!(order_book_collection
+            .expire_gtd_orders(now)
+            .unwrap()
+            .is_empty());
+        let cancel_reports = order_book_collection
+            .expire_gtd_orders(now + one_hour)
+            .unwrap();
+        assert_eq!(
+            cancel_reports,
+            vec![CancelReport {
+                seq_: EventSeq {
+                    seq_num_: 5,
+                    symbol_seq_num_: 5,
+                },
+                order_id_: String::from("3"),
+                symbol_: String::from("REL"),
+                cancelled_qty_: Quantity(100),
+                reason_: CancelReason::GtdExpired,
+            }]
+        );
+
+        let cancel_reports = order_book_collection.expire_day_orders().unwrap();
+        assert_eq!(
+            cancel_reports,
+            vec![CancelReport {
+                seq_: EventSeq {
+                    seq_num_: 6,
+                    symbol_seq_num_: 6,
+                },
+                order_id_: String::from("1"),
+                symbol_: String::from("REL"),
+                cancelled_qty_: Quantity(100),
+                reason_: CancelReason::DayExpired,
+            }]
+        );
+        assert_eq!(
+            queue_order_ids(&mut order_book_collection, OrderSide::Buy, px(100.0)),
+            vec!["2", "4"]
+        );
+
+        //expired order can not be cancelled anymore
+        let cancel_request = CancelRequest {
+            id_: String::from("1"),
+        };
+        let result = process_event(EventType::Cxl(cancel_request), &mut order_book_collection);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn stop_orders_trigger_and_cascade() {
+        let mut order_book_collection = test_engine();
+
+        for (id, price) in [("1", 100.0), ("2", 101.0), ("3", 102.0)] {
+            let order = Order {
+                id_: String::from(id),
+                price_: px(price),
+                symbol_: String::from("REL"),
+                qty_: Quantity(100),
+                side_: OrderSide::Sell,
+                type_: OrderType::Limit,
+                ..Default::default()
+            };
+            let result = process_event(EventType::New(order), &mut order_book_collection);
+            validate_result(&result, 0, px(0.0), None);
+        }
+
+        for (id, stop_price) in [("10", 100.0), ("11", 101.0)] {
+            let order = Order {
+                id_: String::from(id),
+                symbol_: String::from("REL"),
+                qty_: Quantity(100),
+                side_: OrderSide::Buy,
+                type_: OrderType::Stop,
+                stop_price_: px(stop_price),
+                ..Default::default()
+            };
+            let result = process_event(EventType::New(order), &mut order_book_collection);
+            assert!(result.unwrap().triggered_orders().is_empty());
+        }
+
+        //trade at 100 triggers stop 10, its trade at 101 triggers stop 11
+        let order = Order {
+            id_: String::from("20"),
+            price_: px(100.0),
+            symbol_: String::from("REL"),
+            qty_: Quantity(100),
+            side_: OrderSide::Buy,
+            type_: OrderType::Limit,
+            ..Default::default()
+        };
+        let result = process_event(EventType::New(order), &mut order_book_collection);
+        let matched_order_ids = vec![String::from("1")];
+        validate_result(&result, 100, px(100.0), Some(&matched_order_ids));
+
+        let match_result = result.unwrap();
+        let triggered_orders = match_result.triggered_orders();
+        assert_eq!(triggered_orders.len(), 2);
+        for (triggered_order, (id, resting_id, price, trade_id)) in triggered_orders
+            .iter()
+            .zip([("10", "2", 101.0, 2), ("11", "3", 102.0, 3)])
+        {
+            assert_eq!(triggered_order.order_id_, id);
+            assert!(triggered_order.leaves_qty_.is_zero());
+            let fills = triggered_order.match_result_.as_ref().unwrap().fills();
+            assert_eq!(fills.len(), 1);
+            assert_eq!(fills[0].resting_id_, resting_id);
+            assert_eq!(fills[0].price_, px(price));
+            assert_eq!(fills[0].trade_id_, trade_id);
+        }
+
+        let order_book = order_book_collection
+            .get_book_by_symbol(&String::from("REL"))
+            .unwrap();
+        assert!(order_book.asks_.is_empty());
+        assert!(order_book.stop_book_.orders().next().is_none());
+        assert!(order_book_collection.symbol_by_order_id_.is_empty());
+    }
+
+    #[test]
+    fn stops_trigger_on_every_price_printed_by_a_sweep() {
+        let mut order_book_collection = test_engine();
+        for (id, side, price) in [
+            ("1", OrderSide::Sell, 100.0),
+            ("2", OrderSide::Sell, 101.0),
+            ("3", OrderSide::Buy, 99.0),
+        ] {
+            let order = Order {
+                id_: String::from(id),
+                price_: px(price),
+                symbol_: String::from("REL"),
+                qty_: Quantity(100),
+                side_: side,
+                type_: OrderType::Limit,
+                ..Default::default()
+            };
+            let result = process_event(EventType::New(order), &mut order_book_collection);
+            validate_result(&result, 0, px(0.0), None);
+        }
+        let order = Order {
+            id_: String::from("10"),
+            symbol_: String::from("REL"),
+            qty_: Quantity(50),
+            side_: OrderSide::Sell,
+            type_: OrderType::Stop,
+            stop_price_: px(100.2),
+            ..Default::default()
+        };
+        let result = process_event(EventType::New(order), &mut order_book_collection);
+        assert!(result.unwrap().triggered_orders().is_empty());
+
+        //the sweep ends at 101 but its print at 100 went through the sell stop
+        let order = Order {
+            id_: String::from("20"),
+            price_: px(101.0),
+            symbol_: String::from("REL"),
+            qty_: Quantity(200),
+            side_: OrderSide::Buy,
+            type_: OrderType::Limit,
+            ..Default::default()
+        };
+        let result = process_event(EventType::New(order), &mut order_book_collection);
+        let match_result = result.unwrap();
+        assert_eq!(match_result.executed_qty(), Quantity(200));
+        let triggered_orders = match_result.triggered_orders();
+        assert_eq!(triggered_orders.len(), 1);
+        assert_eq!(triggered_orders[0].order_id_, "10");
+        let fills = triggered_orders[0].match_result_.as_ref().unwrap().fills();
+        assert_eq!(fills.len(), 1);
+        assert_eq!(fills[0].resting_id_, "3");
+        assert_eq!(fills[0].price_, px(99.0));
+    }
+
+    #[test]
+    fn resting_stop_orders_can_be_replaced() {
+        let mut order_book_collection = test_engine();
+        for id in ["10", "11"] {
+            let order = Order {
+                id_: String::from(id),
+                price_: px(102.0),
+                symbol_: String::from("REL"),
+                qty_: Quantity(100),
+                side_: OrderSide::Buy,
+                type_: OrderType::StopLimit,
+                stop_price_: px(101.0),
+                ..Default::default()
+            };
+            let result = process_event(EventType::New(order), &mut order_book_collection);
+            assert!(result.is_ok());
+        }
+        let stop_queue = |engine: &MatchingEngine| -> Vec<(String, u64, Price)> {
+            engine.order_book_by_symbol_[&String::from("REL")]
+                .stop_book_
+                .orders()
+                .map(|order| (order.id_.to_owned(), order.qty_.0, order.price_))
+                .collect()
+        };
+        let replace = |qty: u64, price: f64| {
+            EventType::Rpl(ReplaceRequest {
+                id_: String::from("10"),
+                symbol_: String::from("REL"),
+                side_: OrderSide::Buy,
+                qty_: Quantity(qty),
+                price_: px(price),
+            })
+        };
+
+        //qty reduction keeps the trigger priority
+        let result = process_event(replace(60, 102.0), &mut order_book_collection);
+        assert!(result.is_ok());
+        assert_eq!(
+            stop_queue(&order_book_collection),
+            vec![
+                (String::from("10"), 60, px(102.0)),
+                (String::from("11"), 100, px(102.0)),
+            ]
+        );
+
+        //a new limit price goes behind the stops with the same stop price
+        let result = process_event(replace(60, 103.0), &mut order_book_collection);
+        assert!(result.is_ok());
+        assert_eq!(
+            stop_queue(&order_book_collection),
+            vec![
+                (String::from("11"), 100, px(102.0)),
+                (String::from("10"), 60, px(103.0)),
+            ]
+        );
+
+        let cancel_request = CancelRequest {
+            id_: String::from("10"),
+        };
+        let result = process_event(EventType::Cxl(cancel_request), &mut order_book_collection);
+        assert!(result.is_ok());
+        assert_eq!(stop_queue(&order_book_collection).len(), 1);
+    }
+
+    #[test]
+    fn stop_limit_orders_rest_in_trigger_priority() {
+        let mut order_book_collection = test_engine();
+
+        let order = Order {
+            id_: String::from("1"),
+            price_: px(100.0),
+            symbol_: String::from("REL"),
+            qty_: Quantity(100),
+            side_: OrderSide::Sell,
+            type_: OrderType::Limit,
+            ..Default::default()
+        };
+        let result = process_event(EventType::New(order), &mut order_book_collection);
+        validate_result(&result, 0, px(0.0), None);
+
+        //stop 11 arrives later but has the lower stop price, so it is injected first
+        for (id, stop_price, order_type) in [
+            ("10", 100.0, OrderType::StopLimit),
+            ("11", 99.0, OrderType::StopLimit),
+            ("12", 105.0, OrderType::Stop),
+        ] {
+            let order = Order {
+                id_: String::from(id),
+                price_: px(99.0),
+                symbol_: String::from("REL"),
+                qty_: Quantity(50),
+                side_: OrderSide::Buy,
+                type_: order_type,
+                stop_price_: px(stop_price),
+                ..Default::default()
+            };
+            let result = process_event(EventType::New(order), &mut order_book_collection);
+            assert!(result.unwrap().triggered_orders().is_empty());
+        }
+
+        let order = Order {
+            id_: String::from("20"),
+            price_: px(100.0),
+            symbol_: String::from("REL"),
+            qty_: Quantity(100),
+            side_: OrderSide::Buy,
+            type_: OrderType::Limit,
+            ..Default::default()
+        };
+        let result = process_event(EventType::New(order), &mut order_book_collection);
+        let matched_order_ids = vec![String::from("1")];
+        validate_result(&result, 100, px(100.0), Some(&matched_order_ids));
+        let triggered_ids: Vec<String> = result
+            .unwrap()
+            .triggered_orders()
+            .iter()
+            .map(|triggered_order| triggered_order.order_id_.to_owned())
+            .collect();
+        assert_eq!(triggered_ids, vec!["11", "10"]);
+        assert_eq!(
+            queue_order_ids(&mut order_book_collection, OrderSide::Buy, px(99.0)),
+            vec!["11", "10"]
+        );
+
+        //untriggered stop can be cancelled by id
+        let cancel_request = CancelRequest {
+            id_: String::from("12"),
+        };
+        let result = process_event(EventType::Cxl(cancel_request), &mut order_book_collection);
+        assert!(result.is_ok());
+        let order_book = order_book_collection
+            .get_book_by_symbol(&String::from("REL"))
+            .unwrap();
+        assert!(order_book.stop_book_.orders().next().is_none());
+    }
+
+    #[test]
+    fn iceberg_order_shows_peak_and_replenishes() {
+        let mut order_book_collection = test_engine();
+
+        //iceberg of 250 showing 100 at a time, then a plain order at the same price
+        let order = Order {
+            id_: String::from("1"),
+            price_: px(100.0),
+            symbol_: String::from("REL"),
+            qty_: Quantity(250),
+            side_: OrderSide::Sell,
+            type_: OrderType::Limit,
+            display_qty_: Some(Quantity(100)),
+            ..Default::default()
+        };
+        let result = process_event(EventType::New(order), &mut order_book_collection);
+        validate_result(&result, 0, px(0.0), None);
+
+        let order = Order {
+            id_: String::from("2"),
+            price_: px(100.0),
+            symbol_: String::from("REL"),
+            qty_: Quantity(100),
+            side_: OrderSide::Sell,
+            type_: OrderType::Limit,
+            ..Default::default()
+        };
+        let result = process_event(EventType::New(order), &mut order_book_collection);
+        validate_result(&result, 0, px(0.0), None);
+
+        let depth = order_book_collection.depth(&String::from("REL"), OrderSide::Sell, 5);
+        assert_eq!(
+            depth,
+            vec![DepthLevel {
+                price_: px(100.0),
+                qty_: Quantity(200),
+                order_count_: 2,
+            }]
+        );
+
+        //peak of 100 is consumed, the reserve replenishes behind order 2
+        let order = Order {
+            id_: String::from("3"),
+            price_: px(100.0),
+            symbol_: String::from("REL"),
+            qty_: Quantity(150),
+            side_: OrderSide::Buy,
+            type_: OrderType::Limit,
+            ..Default::default()
+        };
+        let result = process_event(EventType::New(order), &mut order_book_collection);
+        let matched_order_ids = vec![String::from("1"), String::from("2")];
+        validate_result(&result, 150, px(100.0), Some(&matched_order_ids));
+        let fills = result.unwrap().fills().clone();
+        assert_eq!(fills[0].qty_, Quantity(100));
+        assert_eq!(fills[0].resting_leaves_qty_, Quantity(150));
+        assert_eq!(fills[1].qty_, Quantity(50));
+        assert_eq!(
+            queue_order_ids(&mut order_book_collection, OrderSide::Sell, px(100.0)),
+            vec!["2", "1"]
+        );
+
+        let depth = order_book_collection.depth(&String::from("REL"), OrderSide::Sell, 5);
+        assert_eq!(depth[0].qty_, Quantity(150));
+
+        //hidden reserve is matchable, the iceberg trades peak by peak till it is filled
+        let order = Order {
+            id_: String::from("4"),
+            price_: px(100.0),
+            symbol_: String::from("REL"),
+            qty_: Quantity(200),
+            side_: OrderSide::Buy,
+            type_: OrderType::Limit,
+            ..Default::default()
+        };
+        let result = process_event(EventType::New(order), &mut order_book_collection);
+        let matched_order_ids = vec![String::from("2"), String::from("1"), String::from("1")];
+        validate_result(&result, 200, px(100.0), Some(&matched_order_ids));
+
+        let order_book = order_book_collection
+            .get_book_by_symbol(&String::from("REL"))
+            .unwrap();
+        assert!(order_book.asks_.is_empty());
+        assert!(order_book.order_index_.is_empty());
+    }
+
+    #[test]
+    fn post_only_order_rejected_or_repriced() {
+        let mut order_book_collection = test_engine();
+
+        let order = Order {
+            id_: String::from("1"),
+            price_: px(100.0),
+            symbol_: String::from("REL"),
+            qty_: Quantity(100),
+            side_: OrderSide::Sell,
+            type_: OrderType::Limit,
+            ..Default::default()
+        };
+        let result = process_event(EventType::New(order), &mut order_book_collection);
+        validate_result(&result, 0, px(0.0), None);
+
+        //would take liquidity at 100, rejected without touching the book
+        let order = Order {
+            id_: String::from("2"),
+            price_: px(100.0),
+            symbol_: String::from("REL"),
+            qty_: Quantity(100),
+            side_: OrderSide::Buy,
+            type_: OrderType::Limit,
+            post_only_: Some(PostOnly::Reject),
+            ..Default::default()
+        };
+        let result = process_event(EventType::New(order), &mut order_book_collection);
+        assert!(result.is_err());
+        assert_eq!(
+            queue_order_ids(&mut order_book_collection, OrderSide::Sell, px(100.0)),
+            vec!["1"]
+        );
+
+        //re-priced one tick behind the best ask and rests there
+        let order = Order {
+            id_: String::from("3"),
+            price_: px(101.0),
+            symbol_: String::from("REL"),
+            qty_: Quantity(100),
+            side_: OrderSide::Buy,
+            type_: OrderType::Limit,
+            post_only_: Some(PostOnly::Reprice),
+            ..Default::default()
+        };
+        let result = process_event(EventType::New(order), &mut order_book_collection);
+        validate_result(&result, 0, px(0.0), None);
+        assert_eq!(result.unwrap().repriced_price(), Some(px(99.99)));
+        assert_eq!(
+            queue_order_ids(&mut order_book_collection, OrderSide::Buy, px(99.99)),
+            vec!["3"]
+        );
+
+        //a post-only order that does not cross rests at its own price
+        let order = Order {
+            id_: String::from("4"),
+            price_: px(99.0),
+            symbol_: String::from("REL"),
+            qty_: Quantity(100),
+            side_: OrderSide::Buy,
+            type_: OrderType::Limit,
+            post_only_: Some(PostOnly::Reprice),
+            ..Default::default()
+        };
+        let result = process_event(EventType::New(order), &mut order_book_collection);
+        validate_result(&result, 0, px(0.0), None);
+        assert_eq!(result.unwrap().repriced_price(), None);
+
+        //replace that would cross keeps the original order in the book
+        let replace_request = ReplaceRequest {
+            id_: String::from("3"),
+            symbol_: String::from("REL"),
+            side_: OrderSide::Buy,
+            qty_: Quantity(100),
+            price_: px(100.0),
+        };
+        let order_book = order_book_collection
+            .get_book_by_symbol(&String::from("REL"))
+            .unwrap();
+        let resting_order = order_book
+            .bids_
+            .get_mut(&px(99.99))
+            .unwrap()
+            .orders_
+            .values_mut()
+            .next()
+            .unwrap();
+        resting_order.post_only_ = Some(PostOnly::Reject);
+        let result = process_event(EventType::Rpl(replace_request), &mut order_book_collection);
+        assert!(result.is_err());
+        assert_eq!(
+            queue_order_ids(&mut order_book_collection, OrderSide::Buy, px(99.99)),
+            vec!["3"]
+        );
+    }
+
+    #[test]
+    fn self_match_prevention_policies() {
+        //(policy, executed qty, aggressor cancelled, resting cancelled, asks left, bids left)
+        let cases = [
+            (
+                SelfMatchPolicy::CancelResting,
+                100,
+                0,
+                100,
+                vec![],
+                vec!["3"],
+            ),
+            (
+                SelfMatchPolicy::CancelAggressor,
+                0,
+                150,
+                0,
+                vec!["1", "2"],
+                vec![],
+            ),
+            (SelfMatchPolicy::CancelBoth, 0, 150, 100, vec!["2"], vec![]),
+            (
+                SelfMatchPolicy::DecrementBoth,
+                50,
+                100,
+                100,
+                vec!["2"],
+                vec![],
+            ),
+        ];
+        for (policy, executed_qty, aggressor_cancelled, resting_cancelled, asks, bids) in cases {
+            let mut order_book_collection = test_engine();
+            order_book_collection.set_self_match_policy(policy);
+
+            for (id, client_id) in [("1", "A"), ("2", "B")] {
+                let order = Order {
+                    id_: String::from(id),
+                    price_: px(100.0),
+                    symbol_: String::from("REL"),
+                    qty_: Quantity(100),
+                    side_: OrderSide::Sell,
+                    type_: OrderType::Limit,
+                    client_id_: Some(String::from(client_id)),
+                    ..Default::default()
+                };
+                let result = process_event(EventType::New(order), &mut order_book_collection);
+                validate_result(&result, 0, px(0.0), None);
+            }
+
+            let order = Order {
+                id_: String::from("3"),
+                price_: px(100.0),
+                symbol_: String::from("REL"),
+                qty_: Quantity(150),
+                side_: OrderSide::Buy,
+                type_: OrderType::Limit,
+                client_id_: Some(String::from("A")),
+                ..Default::default()
+            };
+            let result = process_event(EventType::New(order), &mut order_book_collection);
+            let match_result = result.unwrap();
+            assert_eq!(match_result.executed_qty(), Quantity(executed_qty));
+            assert!(match_result
+                .fills()
+                .iter()
+                .all(|fill| fill.resting_id_ == "2"));
+
+            let reports = match_result.self_match_reports();
+            assert_eq!(reports.len(), 1);
+            assert_eq!(reports[0].policy_, policy);
+            assert_eq!(reports[0].resting_id_, "1");
+            assert_eq!(
+                reports[0].aggressor_cancelled_qty_,
+                Quantity(aggressor_cancelled)
+            );
+            assert_eq!(
+                reports[0].resting_cancelled_qty_,
+                Quantity(resting_cancelled)
+            );
+
+            assert_eq!(
+                queue_order_ids(&mut order_book_collection, OrderSide::Sell, px(100.0)),
+                asks
+            );
+            assert_eq!(
+                queue_order_ids(&mut order_book_collection, OrderSide::Buy, px(100.0)),
+                bids
+            );
+            let mut resting_ids: Vec<&String> =
+                order_book_collection.symbol_by_order_id_.keys().collect();
+            resting_ids.sort();
+            let mut expected_ids: Vec<&str> = asks.iter().chain(bids.iter()).copied().collect();
+            expected_ids.sort();
+            assert_eq!(resting_ids, expected_ids);
+        }
+    }
+
+    #[test]
+    fn pro_rata_allocation_with_top_order_and_minimum() {
+        let mut order_book_collection = engine_with(vec![Instrument {
+            allocation_: Arc::new(allocation::ProRata {
+                top_order_priority_: true,
+                min_allocation_: Quantity(20),
+            }),
+            ..test_instrument("REL")
+        }]);
+
+        for (id, qty) in [("1", 100), ("2", 200), ("3", 300), ("4", 10)] {
+            let order = Order {
+                id_: String::from(id),
+                price_: px(100.0),
+                symbol_: String::from("REL"),
+                qty_: Quantity(qty),
+                side_: OrderSide::Sell,
+                type_: OrderType::Limit,
+                ..Default::default()
+            };
+            let result = process_event(EventType::New(order), &mut order_book_collection);
+            validate_result(&result, 0, px(0.0), None);
+        }
+
+        //top order takes 100, 200 is split 78/117/3 over 510, the 3 is below the minimum
+        //and with the rounding leftover goes to order 2 in time priority
+        let order = Order {
+            id_: String::from("5"),
+            price_: px(100.0),
+            symbol_: String::from("REL"),
+            qty_: Quantity(300),
+            side_: OrderSide::Buy,
+            type_: OrderType::Limit,
+            ..Default::default()
+        };
+        let result = process_event(EventType::New(order), &mut order_book_collection);
+        let matched_order_ids = vec![String::from("1"), String::from("2"), String::from("3")];
+        validate_result(&result, 300, px(100.0), Some(&matched_order_ids));
+        let fill_qtys: Vec<Quantity> = result
+            .unwrap()
+            .fills()
+            .iter()
+            .map(|fill| fill.qty_)
+            .collect();
+        assert_eq!(fill_qtys, vec![Quantity(100), Quantity(83), Quantity(117)]);
+
+        let order_book = order_book_collection
+            .get_book_by_symbol(&String::from("REL"))
+            .unwrap();
+        let leaves_qtys: Vec<Quantity> = order_book.asks_[&px(100.0)]
+            .orders_
+            .values()
+            .map(|order| order.qty_)
+            .collect();
+        assert_eq!(
+            leaves_qtys,
+            vec![Quantity(117), Quantity(183), Quantity(10)]
+        );
+    }
+
+    #[test]
+    fn auction_uncross_at_single_clearing_price() {
+        let mut order_book_collection = test_engine();
+        let symbol = String::from("REL");
+        let admin_request = AdminRequest {
+            symbol_: symbol.to_owned(),
+            trading_state_: TradingState::PreOpen,
+        };
+        let result = process_event(EventType::Adm(admin_request), &mut order_book_collection);
+        validate_result(&result, 0, px(0.0), None);
+
+        //crossing orders accumulate without trading
+        for (id, side, price, qty) in [
+            ("1", OrderSide::Buy, 101.0, 100),
+            ("2", OrderSide::Buy, 100.0, 100),
+            ("3", OrderSide::Buy, 99.0, 100),
+            ("4", OrderSide::Sell, 99.0, 100),
+            ("5", OrderSide::Sell, 100.0, 150),
+            ("6", OrderSide::Sell, 102.0, 100),
+        ] {
+            let order = Order {
+                id_: String::from(id),
+                price_: px(price),
+                symbol_: symbol.to_owned(),
+                qty_: Quantity(qty),
+                side_: side,
+                type_: OrderType::Limit,
+                ..Default::default()
+            };
+            let result = process_event(EventType::New(order), &mut order_book_collection);
+            validate_result(&result, 0, px(0.0), None);
+        }
+
+        let order = Order {
+            id_: String::from("7"),
+            symbol_: symbol.to_owned(),
+            qty_: Quantity(100),
+            side_: OrderSide::Buy,
+            type_: OrderType::Mkt,
+            ..Default::default()
+        };
+        let result = process_event(EventType::New(order), &mut order_book_collection);
+        assert!(result.is_err());
+
+        //200 is executable at 100, every other price executes less
+        let clearing_price = order_book_collection
+            .indicative_uncross(&symbol, None)
+            .unwrap();
+        assert_eq!(clearing_price.price_, px(100.0));
+        assert_eq!(clearing_price.volume_, Quantity(200));
+        assert_eq!(clearing_price.imbalance_, Quantity(50));
+
+        let result = order_book_collection.uncross_auction(&symbol, None);
+        let matched_order_ids = vec![String::from("4"), String::from("5")];
+        validate_result(&result, 200, px(100.0), Some(&matched_order_ids));
+        let fills = result.unwrap().fills().clone();
+        assert_eq!(fills[0].aggressor_id_, "1");
+        assert_eq!(fills[1].aggressor_id_, "2");
+        assert_eq!(fills[1].resting_leaves_qty_, Quantity(50));
+        assert_eq!(
+            queue_order_ids(&mut order_book_collection, OrderSide::Sell, px(100.0)),
+            vec!["5"]
+        );
+        let mut resting_ids: Vec<&String> =
+            order_book_collection.symbol_by_order_id_.keys().collect();
+        resting_ids.sort();
+        assert_eq!(resting_ids, vec!["3", "5", "6"]);
+
+        //continuous trading after the uncross
+        let order = Order {
+            id_: String::from("8"),
+            price_: px(100.0),
+            symbol_: symbol.to_owned(),
+            qty_: Quantity(50),
+            side_: OrderSide::Buy,
+            type_: OrderType::Limit,
+            ..Default::default()
+        };
+        let result = process_event(EventType::New(order), &mut order_book_collection);
+        let matched_order_ids = vec![String::from("5")];
+        validate_result(&result, 50, px(100.0), Some(&matched_order_ids));
+        assert!(order_book_collection
+            .uncross_auction(&symbol, None)
+            .is_err());
+    }
+
+    #[test]
+    fn auction_uncross_prevents_self_matches() {
+        let mut order_book_collection = test_engine();
+        let symbol = String::from("REL");
+        let admin_request = AdminRequest {
+            symbol_: symbol.to_owned(),
+            trading_state_: TradingState::PreOpen,
+        };
+        let result = process_event(EventType::Adm(admin_request), &mut order_book_collection);
+        validate_result(&result, 0, px(0.0), None);
+
+        for (id, side, qty, client_id) in [
+            ("1", OrderSide::Buy, 100, "X"),
+            ("2", OrderSide::Sell, 100, "X"),
+            ("3", OrderSide::Sell, 50, "Y"),
+        ] {
+            let order = Order {
+                id_: String::from(id),
+                price_: px(100.0),
+                symbol_: symbol.to_owned(),
+                qty_: Quantity(qty),
+                side_: side,
+                type_: OrderType::Limit,
+                client_id_: Some(String::from(client_id)),
+                ..Default::default()
+            };
+            let result = process_event(EventType::New(order), &mut order_book_collection);
+            validate_result(&result, 0, px(0.0), None);
+        }
+
+        //the resting sell of client X is cancelled, the buy trades with client Y only
+        let result = order_book_collection.uncross_auction(&symbol, None);
+        let matched_order_ids = vec![String::from("3")];
+        validate_result(&result, 50, px(100.0), Some(&matched_order_ids));
+        let match_result = result.unwrap();
+        assert_eq!(match_result.fills()[0].aggressor_leaves_qty_, Quantity(50));
+        let reports = match_result.self_match_reports();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].aggressor_id_, "1");
+        assert_eq!(reports[0].resting_id_, "2");
+        assert_eq!(reports[0].policy_, SelfMatchPolicy::CancelResting);
+        assert_eq!(reports[0].resting_cancelled_qty_, Quantity(100));
+
+        assert_eq!(
+            queue_order_ids(&mut order_book_collection, OrderSide::Buy, px(100.0)),
+            vec!["1"]
+        );
+        assert!(order_book_collection.order_book_by_symbol_[&symbol]
+            .asks_
+            .is_empty());
+        let resting_ids: Vec<&String> = order_book_collection.symbol_by_order_id_.keys().collect();
+        assert_eq!(resting_ids, vec!["1"]);
+    }
+
+    #[test]
+    fn auction_reference_price_breaks_ties() {
+        let mut order_book_collection = test_engine();
+        let symbol = String::from("REL");
+        let admin_request = AdminRequest {
+            symbol_: symbol.to_owned(),
+            trading_state_: TradingState::PreOpen,
+        };
+        let result = process_event(EventType::Adm(admin_request), &mut order_book_collection);
+        validate_result(&result, 0, px(0.0), None);
+
+        for (id, side, price) in [("1", OrderSide::Buy, 101.0), ("2", OrderSide::Sell, 100.0)] {
+            let order = Order {
+                id_: String::from(id),
+                price_: px(price),
+                symbol_: symbol.to_owned(),
+                qty_: Quantity(100),
+                side_: side,
+                type_: OrderType::Limit,
+                ..Default::default()
+            };
+            let result = process_event(EventType::New(order), &mut order_book_collection);
+            validate_result(&result, 0, px(0.0), None);
+        }
+
+        //same volume and imbalance at 100 and 101
+        let clearing_price = order_book_collection.indicative_uncross(&symbol, None);
+        assert_eq!(clearing_price.unwrap().price_, px(100.0));
+        let clearing_price = order_book_collection.indicative_uncross(&symbol, Some(px(105.0)));
+        assert_eq!(clearing_price.unwrap().price_, px(101.0));
+
+        let result = order_book_collection.uncross_auction(&symbol, Some(px(105.0)));
+        let matched_order_ids = vec![String::from("2")];
+        validate_result(&result, 100, px(101.0), Some(&matched_order_ids));
+    }
+
+    #[test]
+    fn crossed_book_is_uncrossed_when_reopened_after_halt() {
+        for interim_state in [TradingState::Halted, TradingState::Closed] {
+            let mut order_book_collection = test_engine();
+            let admin_request = |trading_state: TradingState| AdminRequest {
+                symbol_: String::from("REL"),
+                trading_state_: trading_state,
+            };
+            let result = process_event(
+                EventType::Adm(admin_request(TradingState::PreOpen)),
+                &mut order_book_collection,
+            );
+            assert!(result.is_ok());
+
+            for (id, side) in [("1", OrderSide::Sell), ("2", OrderSide::Buy)] {
+                let order = Order {
+                    id_: String::from(id),
+                    price_: px(100.0),
+                    symbol_: String::from("REL"),
+                    qty_: Quantity(100),
+                    side_: side,
+                    type_: OrderType::Limit,
+                    ..Default::default()
+                };
+                let result = process_event(EventType::New(order), &mut order_book_collection);
+                validate_result(&result, 0, px(0.0), None);
+            }
+
+            let result = process_event(
+                EventType::Adm(admin_request(interim_state)),
+                &mut order_book_collection,
+            );
+            assert!(result.is_ok());
+
+            let result = process_event(
+                EventType::Adm(admin_request(TradingState::Open)),
+                &mut order_book_collection,
+            );
+            let matched_order_ids = vec![String::from("1")];
+            validate_result(&result, 100, px(100.0), Some(&matched_order_ids));
+            let match_result = result.unwrap();
+            let state_change = match_result.trading_state_change().unwrap();
+            assert_eq!(state_change.previous_state_, interim_state);
+            assert_eq!(state_change.trading_state_, TradingState::Open);
+
+            let order_book = order_book_collection
+                .get_book_by_symbol(&String::from("REL"))
+                .unwrap();
+            assert!(order_book.bids_.is_empty() && order_book.asks_.is_empty());
+            assert!(order_book_collection.symbol_by_order_id_.is_empty());
+        }
+    }
+
+    #[test]
+    fn trading_state_decides_accepted_events() {
+        let mut order_book_collection = test_engine();
+        let symbol = String::from("REL");
+        let new_order = |id: &str, side: OrderSide| Order {
+            id_: String::from(id),
+            price_: px(100.0),
+            symbol_: String::from("REL"),
+            qty_: Quantity(100),
+            side_: side,
+            type_: OrderType::Limit,
+            ..Default::default()
+        };
+        let admin_request = |trading_state: TradingState| AdminRequest {
+            symbol_: String::from("REL"),
+            trading_state_: trading_state,
+        };
+
+        let result = process_event(
+            EventType::New(new_order("1", OrderSide::Sell)),
+            &mut order_book_collection,
+        );
+        validate_result(&result, 0, px(0.0), None);
+
+        //halted book takes cancels only
+        let result = process_event(
+            EventType::Adm(admin_request(TradingState::Halted)),
+            &mut order_book_collection,
+        );
+        let match_result = result.unwrap();
+        assert_eq!(
+            match_result.trading_state_change(),
+            Some(&TradingStateChange {
+                seq_: EventSeq {
+                    seq_num_: 3,
+                    symbol_seq_num_: 3,
+                },
+                symbol_: symbol.to_owned(),
+                previous_state_: TradingState::Open,
+                trading_state_: TradingState::Halted,
+            })
+        );
+        let result = process_event(
+            EventType::New(new_order("2", OrderSide::Buy)),
+            &mut order_book_collection,
+        );
+        assert_eq!(
+            result.unwrap_err().reason_,
+            EngineError::BookHalted {
+                symbol_: symbol.to_owned()
+            }
+        );
+        let replace_request = ReplaceRequest {
+            id_: String::from("1"),
+            symbol_: symbol.to_owned(),
+            side_: OrderSide::Sell,
+            qty_: Quantity(50),
+            price_: px(100.0),
+        };
+        let result = process_event(EventType::Rpl(replace_request), &mut order_book_collection);
+        assert_eq!(
+            result.unwrap_err().reason_,
+            EngineError::BookHalted {
+                symbol_: symbol.to_owned()
+            }
+        );
+
+        //pre-open accepts orders without matching, open uncrosses them
+        let result = process_event(
+            EventType::Adm(admin_request(TradingState::PreOpen)),
+            &mut order_book_collection,
+        );
+        validate_result(&result, 0, px(0.0), None);
+        let result = process_event(
+            EventType::New(new_order("2", OrderSide::Buy)),
+            &mut order_book_collection,
+        );
+        validate_result(&result, 0, px(0.0), None);
+        let result = process_event(
+            EventType::Adm(admin_request(TradingState::Open)),
+            &mut order_book_collection,
+        );
+        let matched_order_ids = vec![String::from("1")];
+        validate_result(&result, 100, px(100.0), Some(&matched_order_ids));
+        assert_eq!(
+            result
+                .unwrap()
+                .trading_state_change()
+                .unwrap()
+                .trading_state_,
+            TradingState::Open
+        );
+
+        //closed book rejects everything, cancels included
+        let result = process_event(
+            EventType::New(new_order("3", OrderSide::Buy)),
+            &mut order_book_collection,
+        );
+        validate_result(&result, 0, px(0.0), None);
+        let result = process_event(
+            EventType::Adm(admin_request(TradingState::Closed)),
+            &mut order_book_collection,
+        );
+        validate_result(&result, 0, px(0.0), None);
+        let cancel_request = CancelRequest {
+            id_: String::from("3"),
+        };
+        let result = process_event(EventType::Cxl(cancel_request), &mut order_book_collection);
+        assert_eq!(
+            result.unwrap_err().reason_,
+            EngineError::BookClosed {
+                symbol_: symbol.to_owned()
+            }
+        );
+        let result = process_event(
+            EventType::Adm(admin_request(TradingState::Halted)),
+            &mut order_book_collection,
+        );
+        assert_eq!(
+            result.unwrap_err().reason_,
+            EngineError::InvalidStateTransition {
+                symbol_: symbol.to_owned(),
+                from_: TradingState::Closed,
+                to_: TradingState::Halted,
+            }
+        );
+        assert_eq!(
+            queue_order_ids(&mut order_book_collection, OrderSide::Buy, px(100.0)),
+            vec!["3"]
+        );
+    }
+
+    #[test]
+    fn price_band_rejects_orders_trading_outside() {
+        let mut order_book_collection = engine_with(vec![Instrument {
+            price_band_: Some(PriceBand {
+                width_bps_: 500,
+                breach_action_: BandBreachAction::Reject,
+                previous_close_: Some(px(100.0)),
+            }),
+            ..test_instrument("REL")
+        }]);
+
+        for (id, price) in [("1", 100.0), ("2", 104.0), ("3", 106.0)] {
+            let order = Order {
+                id_: String::from(id),
+                price_: px(price),
+                symbol_: String::from("REL"),
+                qty_: Quantity(100),
+                side_: OrderSide::Sell,
+                type_: OrderType::Limit,
+                ..Default::default()
+            };
+            let result = process_event(EventType::New(order), &mut order_book_collection);
+            validate_result(&result, 0, px(0.0), None);
+        }
+
+        //the sweep would reach 106, band is 95 to 105 around the previous close
+        let mkt_order = |qty: u64| Order {
+            id_: String::from("4"),
+            symbol_: String::from("REL"),
+            qty_: Quantity(qty),
+            side_: OrderSide::Buy,
+            type_: OrderType::Mkt,
+            ..Default::default()
+        };
+        let result = process_event(EventType::New(mkt_order(300)), &mut order_book_collection);
+        assert!(result.is_err());
+        assert_eq!(
+            queue_order_ids(&mut order_book_collection, OrderSide::Sell, px(100.0)),
+            vec!["1"]
+        );
+
+        let result = process_event(EventType::New(mkt_order(200)), &mut order_book_collection);
+        let matched_order_ids = vec![String::from("1"), String::from("2")];
+        validate_result(&result, 200, px(102.0), Some(&matched_order_ids));
+
+        //band follows the last trade at 104
+        let result = process_event(EventType::New(mkt_order(100)), &mut order_book_collection);
+        let matched_order_ids = vec![String::from("3")];
+        validate_result(&result, 100, px(106.0), Some(&matched_order_ids));
+    }
+
+    #[test]
+    fn price_band_counts_qty_behind_own_client_orders() {
+        let mut order_book_collection = engine_with(vec![Instrument {
+            price_band_: Some(PriceBand {
+                width_bps_: 500,
+                breach_action_: BandBreachAction::Reject,
+                previous_close_: Some(px(100.0)),
+            }),
+            ..test_instrument("REL")
+        }]);
+
+        for (id, price, client_id) in [("1", 100.0, "A"), ("2", 150.0, "B")] {
+            let order = Order {
+                id_: String::from(id),
+                price_: px(price),
+                symbol_: String::from("REL"),
+                qty_: Quantity(100),
+                side_: OrderSide::Sell,
+                type_: OrderType::Limit,
+                client_id_: Some(String::from(client_id)),
+                ..Default::default()
+            };
+            let result = process_event(EventType::New(order), &mut order_book_collection);
+            validate_result(&result, 0, px(0.0), None);
+        }
+
+        //own order at 100 is cancelled, the buy would trade at 150 outside the band
+        let order = Order {
+            id_: String::from("3"),
+            price_: px(200.0),
+            symbol_: String::from("REL"),
+            qty_: Quantity(100),
+            side_: OrderSide::Buy,
+            type_: OrderType::Limit,
+            client_id_: Some(String::from("A")),
+            ..Default::default()
+        };
+        let result = process_event(EventType::New(order), &mut order_book_collection);
+        assert!(matches!(
+            result.unwrap_err().reason_,
+            EngineError::PriceBandBreach { .. }
+        ));
+        assert_eq!(
+            queue_order_ids(&mut order_book_collection, OrderSide::Sell, px(100.0)),
+            vec!["1"]
+        );
+        assert_eq!(
+            queue_order_ids(&mut order_book_collection, OrderSide::Sell, px(150.0)),
+            vec!["2"]
+        );
+    }
+
+    #[test]
+    fn triggered_stop_breaching_the_band_keeps_the_event_fills() {
+        let mut order_book_collection = engine_with(vec![Instrument {
+            price_band_: Some(PriceBand {
+                width_bps_: 100,
+                breach_action_: BandBreachAction::Reject,
+                previous_close_: Some(px(100.0)),
+            }),
+            ..test_instrument("REL")
+        }]);
+        let symbol = String::from("REL");
+        for (id, side, order_type, price, stop_price) in [
+            ("a1", OrderSide::Sell, OrderType::Limit, 100.0, 0.0),
+            ("a2", OrderSide::Sell, OrderType::Limit, 105.0, 0.0),
+            ("s1", OrderSide::Buy, OrderType::Stop, 0.0, 100.0),
+        ] {
+            let order = Order {
+                id_: String::from(id),
+                price_: px(price),
+                symbol_: symbol.to_owned(),
+                qty_: Quantity(100),
+                side_: side,
+                type_: order_type,
+                stop_price_: px(stop_price),
+                ..Default::default()
+            };
+            let result = process_event(EventType::New(order), &mut order_book_collection);
+            validate_result(&result, 0, px(0.0), None);
+        }
+
+        //the stop would trade at 105, only the stop is rejected
+        let order = Order {
+            id_: String::from("b1"),
+            price_: px(100.0),
+            symbol_: symbol.to_owned(),
+            qty_: Quantity(100),
+            side_: OrderSide::Buy,
+            type_: OrderType::Limit,
+            ..Default::default()
+        };
+        let result = process_event(EventType::New(order), &mut order_book_collection);
+        let matched_order_ids = vec![String::from("a1")];
+        validate_result(&result, 100, px(100.0), Some(&matched_order_ids));
+        let match_result = result.unwrap();
+        assert_eq!(match_result.fills()[0].trade_id_, 1);
+        let triggered_orders = match_result.triggered_orders();
+        assert_eq!(triggered_orders.len(), 1);
+        assert_eq!(triggered_orders[0].order_id_, "s1");
+        assert!(triggered_orders[0].leaves_qty_.is_zero());
+        assert!(matches!(
+            triggered_orders[0].reject_reason_,
+            Some(EngineError::PriceBandBreach { .. })
+        ));
+
+        let resting_ids: Vec<&String> = order_book_collection.symbol_by_order_id_.keys().collect();
+        assert_eq!(resting_ids, vec!["a2"]);
+        assert_eq!(
+            queue_order_ids(&mut order_book_collection, OrderSide::Sell, px(105.0)),
+            vec!["a2"]
+        );
+        assert_eq!(
+            order_book_collection.order_book_by_symbol_[&symbol]
+                .asks_
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn volatility_auction_started_by_a_stop_parks_the_trigger_chain() {
+        let mut order_book_collection = engine_with(vec![Instrument {
+            price_band_: Some(PriceBand {
+                width_bps_: 100,
+                breach_action_: BandBreachAction::VolatilityHalt,
+                previous_close_: Some(px(100.0)),
+            }),
+            ..test_instrument("REL")
+        }]);
+        let symbol = String::from("REL");
+        for (id, side, order_type, qty, price, stop_price) in [
+            ("a1", OrderSide::Sell, OrderType::Limit, 100, 100.0, 0.0),
+            ("a2", OrderSide::Sell, OrderType::Limit, 50, 100.5, 0.0),
+            ("a3", OrderSide::Sell, OrderType::Limit, 100, 105.0, 0.0),
+            ("s1", OrderSide::Buy, OrderType::Stop, 100, 0.0, 100.0),
+            ("s2", OrderSide::Buy, OrderType::Stop, 50, 0.0, 100.0),
+        ] {
+            let order = Order {
+                id_: String::from(id),
+                price_: px(price),
+                symbol_: symbol.to_owned(),
+                qty_: Quantity(qty),
+                side_: side,
+                type_: order_type,
+                stop_price_: px(stop_price),
+                ..Default::default()
+            };
+            let result = process_event(EventType::New(order), &mut order_book_collection);
+            validate_result(&result, 0, px(0.0), None);
+        }
+
+        //s1 would sweep up to 105 and halts the book, s2 is not injected in the auction
+        let order = Order {
+            id_: String::from("b1"),
+            price_: px(100.0),
+            symbol_: symbol.to_owned(),
+            qty_: Quantity(100),
+            side_: OrderSide::Buy,
+            type_: OrderType::Limit,
+            ..Default::default()
+        };
+        let result = process_event(EventType::New(order), &mut order_book_collection);
+        let matched_order_ids = vec![String::from("a1")];
+        validate_result(&result, 100, px(100.0), Some(&matched_order_ids));
+        let match_result = result.unwrap();
+        let triggered_orders = match_result.triggered_orders();
+        assert_eq!(triggered_orders.len(), 1);
+        assert_eq!(triggered_orders[0].order_id_, "s1");
+        assert_eq!(triggered_orders[0].leaves_qty_, Quantity(100));
+        let stop_result = triggered_orders[0].match_result_.as_ref().unwrap();
+        assert!(stop_result.fills().is_empty());
+        assert_eq!(
+            stop_result.trading_state_change().unwrap().trading_state_,
+            TradingState::PreOpen
+        );
+
+        //both stops wait in the trigger book in their arrival order, the asks are untouched
+        let stop_ids = |engine: &MatchingEngine| -> Vec<String> {
+            engine.order_book_by_symbol_[&symbol]
+                .stop_book_
+                .orders()
+                .map(|order| order.id_.to_owned())
+                .collect()
+        };
+        assert_eq!(stop_ids(&order_book_collection), vec!["s1", "s2"]);
+        assert_eq!(
+            queue_order_ids(&mut order_book_collection, OrderSide::Sell, px(100.5)),
+            vec!["a2"]
+        );
+        let order_book = &order_book_collection.order_book_by_symbol_[&symbol];
+        assert_eq!(order_book.trading_state_, TradingState::PreOpen);
+        assert!(order_book_collection.symbol_by_order_id_.contains_key("s2"));
+
+        //no bids to uncross with, the book opens without a print and the stops keep waiting
+        let result = order_book_collection.uncross_auction(&symbol, None);
+        validate_result(&result, 0, px(0.0), None);
+        assert_eq!(stop_ids(&order_book_collection), vec!["s1", "s2"]);
+    }
+
+    #[test]
+    fn price_band_breach_starts_volatility_auction() {
+        let mut order_book_collection = engine_with(vec![Instrument {
+            price_band_: Some(PriceBand {
+                width_bps_: 500,
+                breach_action_: BandBreachAction::VolatilityHalt,
+                previous_close_: Some(px(100.0)),
+            }),
+            ..test_instrument("REL")
+        }]);
+        let symbol = String::from("REL");
+
+        for (id, price) in [("1", 100.0), ("2", 110.0)] {
+            let order = Order {
+                id_: String::from(id),
+                price_: px(price),
+                symbol_: symbol.to_owned(),
+                qty_: Quantity(100),
+                side_: OrderSide::Sell,
+                type_: OrderType::Limit,
+                ..Default::default()
+            };
+            let result = process_event(EventType::New(order), &mut order_book_collection);
+            validate_result(&result, 0, px(0.0), None);
+        }
+
+        //nothing trades, the order waits in the volatility auction
+        let order = Order {
+            id_: String::from("3"),
+            price_: px(110.0),
+            symbol_: symbol.to_owned(),
+            qty_: Quantity(200),
+            side_: OrderSide::Buy,
+            type_: OrderType::Limit,
+            ..Default::default()
+        };
+        let result = process_event(EventType::New(order), &mut order_book_collection);
+        validate_result(&result, 0, px(0.0), None);
+        assert_eq!(
+            result
+                .unwrap()
+                .trading_state_change()
+                .unwrap()
+                .trading_state_,
+            TradingState::PreOpen
+        );
+        assert_eq!(
+            queue_order_ids(&mut order_book_collection, OrderSide::Buy, px(110.0)),
+            vec!["3"]
+        );
+
+        let admin_request = AdminRequest {
+            symbol_: symbol.to_owned(),
+            trading_state_: TradingState::Open,
+        };
+        let result = process_event(EventType::Adm(admin_request), &mut order_book_collection);
+        let matched_order_ids = vec![String::from("1"), String::from("2")];
+        validate_result(&result, 200, px(110.0), Some(&matched_order_ids));
+    }
+
+    #[test]
+    fn instrument_converts_prices_in_its_scale() {
+        let instrument = Instrument {
+            price_scale_: 4,
+            ..test_instrument("REL")
+        };
+        let price = instrument.price_from_f64(100.1234).unwrap();
+        assert_eq!(price, Price::from_f64(100.1234, 4).unwrap());
+        assert_eq!(instrument.price_to_f64(price), 100.1234);
+        assert_eq!(
+            test_instrument("TCS").price_from_f64(100.1234),
+            Price::from_f64(100.1234, PRICE_SCALE)
+        );
+    }
+
+    #[test]
+    fn instrument_registry_validates_orders() {
+        let mut order_book_collection = engine_with(vec![Instrument {
+            tick_size_: px(0.05),
+            lot_size_: Quantity(10),
+            min_qty_: Quantity(10),
+            max_qty_: Quantity(1000),
+            ..test_instrument("REL")
+        }]);
+        let order = |symbol: &str, price: f64, qty: u64| Order {
+            id_: String::from("1"),
+            price_: px(price),
+            symbol_: String::from(symbol),
+            qty_: Quantity(qty),
+            side_: OrderSide::Buy,
+            type_: OrderType::Limit,
+            ..Default::default()
+        };
+
+        //unknown symbol does not create an order book
+        let result = process_event(
+            EventType::New(order("RELL", 100.0, 100)),
+            &mut order_book_collection,
+        );
+        assert!(matches!(
+            result,
+            Err(Reject {
+                reason_: EngineError::UnknownSymbol { .. },
+                ..
+            })
+        ));
+        assert!(!order_book_collection.contains(&String::from("RELL")));
+
+        let result = process_event(
+            EventType::New(order("REL", 100.02, 100)),
+            &mut order_book_collection,
+        );
+        assert!(matches!(
+            result,
+            Err(Reject {
+                reason_: EngineError::InvalidPrice { .. },
+                ..
+            })
+        ));
+        let result = process_event(
+            EventType::New(order("REL", 100.0, 105)),
+            &mut order_book_collection,
+        );
+        assert!(matches!(
+            result,
+            Err(Reject {
+                reason_: EngineError::InvalidLot { .. },
+                ..
+            })
+        ));
+        let result = process_event(
+            EventType::New(order("REL", 100.0, 2000)),
+            &mut order_book_collection,
+        );
+        assert!(matches!(
+            result,
+            Err(Reject {
+                reason_: EngineError::QtyOutOfRange { .. },
+                ..
+            })
+        ));
+
+        let result = process_event(
+            EventType::New(order("REL", 100.05, 100)),
+            &mut order_book_collection,
+        );
+        validate_result(&result, 0, px(0.0), None);
+
+        //replace is validated like a new order
+        let replace_request = ReplaceRequest {
+            id_: String::from("1"),
+            symbol_: String::from("REL"),
+            side_: OrderSide::Buy,
+            qty_: Quantity(100),
+            price_: px(100.01),
+        };
+        let result = process_event(EventType::Rpl(replace_request), &mut order_book_collection);
+        assert!(matches!(
+            result,
+            Err(Reject {
+                reason_: EngineError::InvalidPrice { .. },
+                ..
+            })
+        ));
+        assert_eq!(
+            queue_order_ids(&mut order_book_collection, OrderSide::Buy, px(100.05)),
+            vec!["1"]
+        );
+    }
+
+    #[test]
+    fn every_output_is_sequenced_per_engine_and_symbol() {
+        let mut order_book_collection = test_engine();
+        let seq = |seq_num: u64, symbol_seq_num: u64| EventSeq {
+            seq_num_: seq_num,
+            symbol_seq_num_: symbol_seq_num,
+        };
+        let order = |id: &str, symbol: &str, qty: u64, side: OrderSide| Order {
+            id_: String::from(id),
+            price_: px(100.0),
+            symbol_: String::from(symbol),
+            qty_: Quantity(qty),
+            side_: side,
+            type_: OrderType::Limit,
+            ..Default::default()
+        };
+
+        let result = process_event(
+            EventType::New(order("1", "REL", 100, OrderSide::Sell)),
+            &mut order_book_collection,
+        );
+        assert_eq!(result.unwrap().seq(), seq(1, 1));
+        let result = process_event(
+            EventType::New(order("2", "TCS", 100, OrderSide::Buy)),
+            &mut order_book_collection,
+        );
+        assert_eq!(result.unwrap().seq(), seq(2, 1));
+
+        //rejects take the next engine sequence number, unknown symbols have no symbol stream
+        let result = process_event(
+            EventType::New(order("3", "RELL", 100, OrderSide::Buy)),
+            &mut order_book_collection,
+        );
+        assert_eq!(result.unwrap_err().seq_, seq(3, 0));
+
+        //fills are sequenced after the ack of their aggressor
+        let result = process_event(
+            EventType::New(order("4", "REL", 150, OrderSide::Buy)),
+            &mut order_book_collection,
+        );
+        let match_result = result.unwrap();
+        assert_eq!(match_result.seq(), seq(4, 2));
+        assert_eq!(match_result.fills()[0].seq_, seq(5, 3));
+
+        let cancel_request = CancelRequest {
+            id_: String::from("4"),
+        };
+        let result = process_event(
+            EventType::Cxl(cancel_request.clone()),
+            &mut order_book_collection,
+        );
+        assert_eq!(result.unwrap().seq(), seq(6, 4));
+        let result = process_event(EventType::Cxl(cancel_request), &mut order_book_collection);
+        assert_eq!(result.unwrap_err().seq_, seq(7, 0));
+
+        assert_eq!(order_book_collection.last_seq_num(), 7);
+        assert_eq!(
+            order_book_collection.last_symbol_seq_num(&String::from("REL")),
+            Some(4)
+        );
+        assert_eq!(
+            order_book_collection.last_symbol_seq_num(&String::from("TCS")),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn engine_clock_stamps_entry_time() {
+        let clock = Arc::new(ManualClock::new(5_000));
+        let mut order_book_collection =
+            MatchingEngine::with_clock(test_registry(vec![test_instrument("REL")]), clock.clone());
+        let order = |id: &str, price: f64| Order {
+            id_: String::from(id),
+            price_: px(price),
+            symbol_: String::from("REL"),
+            qty_: Quantity(100),
+            side_: OrderSide::Buy,
+            type_: OrderType::Limit,
+            //whatever the caller sets is overwritten on entry
+            entry_time_: 42,
+            ..Default::default()
+        };
+
+        let result = process_event(
+            EventType::New(order("1", 100.0)),
+            &mut order_book_collection,
+        );
+        validate_result(&result, 0, px(0.0), None);
+        clock.advance(250);
+        let result = process_event(
+            EventType::New(order("2", 101.0)),
+            &mut order_book_collection,
+        );
+        validate_result(&result, 0, px(0.0), None);
+        //a clock can not be moved backwards
+        clock.set(10);
+        let result = process_event(
+            EventType::New(order("3", 102.0)),
+            &mut order_book_collection,
+        );
+        validate_result(&result, 0, px(0.0), None);
+
+        let order_book = order_book_collection
+            .get_book_by_symbol(&String::from("REL"))
+            .unwrap();
+        let entry_times: Vec<u64> = ["1", "2", "3"]
+            .iter()
+            .map(|id| {
+                order_book
+                    .get_order(&String::from(*id))
+                    .unwrap()
+                    .entry_time_
+            })
+            .collect();
+        assert_eq!(entry_times, vec![5_000, 5_250, 5_250]);
+    }
+
+    //id, qty, priority and entry time of a resting order
+    type RestingOrderState = (String, Quantity, u64, u64);
+    //symbol, last sequence number, last priority and the resting orders of a book
+    type BookState = (String, u64, u64, Vec<RestingOrderState>);
+
+    //Everything a replay has to rebuild: sequence numbers, trade ids and the resting orders
+    //of every book with their priority and entry time
+    fn engine_state(p_engine: &MatchingEngine) -> (u64, u64, Vec<BookState>) {
+        let mut symbols: Vec<&String> = p_engine.order_book_by_symbol_.keys().collect();
+        symbols.sort();
+        let books = symbols
+            .into_iter()
+            .map(|symbol| {
+                let order_book = &p_engine.order_book_by_symbol_[symbol];
+                let orders = order_book
+                    .bids_
+                    .values()
+                    .rev()
+                    .chain(order_book.asks_.values())
+                    .flat_map(|level| level.orders_.values())
+                    .map(|order| {
+                        (
+                            order.id_.to_owned(),
+                            order.qty_,
+                            order.priority_seq_,
+                            order.entry_time_,
+                        )
+                    })
+                    .collect();
+                (
+                    symbol.to_owned(),
+                    order_book.last_seq_num_,
+                    order_book.last_priority_seq_,
+                    orders,
+                )
+            })
+            .collect();
+        (p_engine.last_seq_num_, p_engine.last_trade_id_, books)
+    }
+
+    #[test]
+    fn journal_replay_rebuilds_books_and_sequence_numbers() {
+        let journal_path = std::env::temp_dir().join(format!(
+            "matching_engine_journal_{}.log",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&journal_path);
+        let registry = || test_registry(vec![test_instrument("REL"), test_instrument("TCS")]);
+        let order = |id: &str, symbol: &str, side: OrderSide, qty: u64, price: f64| Order {
+            id_: String::from(id),
+            price_: px(price),
+            symbol_: String::from(symbol),
+            qty_: Quantity(qty),
+            side_: side,
+            type_: OrderType::Limit,
+            ..Default::default()
+        };
+
+        let clock = Arc::new(ManualClock::new(1_000));
+        let mut order_book_collection = MatchingEngine::with_clock(registry(), clock.clone());
+        let replayed_records = order_book_collection
+            .open_journal(&journal_path, FsyncPolicy::EveryRecord)
+            .unwrap();
+        assert_eq!(replayed_records, 0);
+
+        let events = vec![
+            EventType::New(order("1", "REL", OrderSide::Sell, 100, 100.0)),
+            EventType::New(order("2", "REL", OrderSide::Sell, 100, 101.0)),
+            EventType::New(order("3", "REL", OrderSide::Buy, 150, 101.0)),
+            EventType::New(order("4", "RELL", OrderSide::Buy, 100, 101.0)),
+            EventType::New(Order {
+                time_in_force_: TimeInForce::Day,
+                ..order("5", "REL", OrderSide::Buy, 50, 98.0)
+            }),
+            EventType::New(order("6", "TCS", OrderSide::Buy, 100, 99.0)),
+            EventType::Rpl(ReplaceRequest {
+                id_: String::from("6"),
+                symbol_: String::from("TCS"),
+                side_: OrderSide::Buy,
+                qty_: Quantity(200),
+                price_: px(99.0),
+            }),
+            EventType::Cxl(CancelRequest {
+                id_: String::from("99"),
+            }),
+            EventType::Adm(AdminRequest {
+                symbol_: String::from("TCS"),
+                trading_state_: TradingState::PreOpen,
+            }),
+        ];
+        for event in events {
+            clock.advance(10);
+            let _ = process_event(event, &mut order_book_collection);
+        }
+        let cancel_reports = order_book_collection.expire_day_orders().unwrap();
+        assert_eq!(cancel_reports.len(), 1);
+        let expected_state = engine_state(&order_book_collection);
+        drop(order_book_collection);
+
+        //crash in the middle of a write leaves a torn record at the end
+        let mut journal_file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&journal_path)
+            .unwrap();
+        std::io::Write::write_all(&mut journal_file, &[9, 0, 0, 0, 1]).unwrap();
+        drop(journal_file);
+
+        //restarted clock is behind the journal, entry times still move forward
+        let mut order_book_collection =
+            MatchingEngine::with_clock(registry(), Arc::new(ManualClock::new(0)));
+        let replayed_records = order_book_collection
+            .open_journal(&journal_path, FsyncPolicy::EveryN(8))
+            .unwrap();
+        assert_eq!(replayed_records, 10);
+        assert_eq!(engine_state(&order_book_collection), expected_state);
+        assert_eq!(
+            order_book_collection
+                .get_book_by_symbol(&String::from("TCS"))
+                .unwrap()
+                .trading_state_,
+            TradingState::PreOpen
+        );
+
+        let result = process_event(
+            EventType::New(order("7", "REL", OrderSide::Buy, 10, 95.0)),
+            &mut order_book_collection,
+        );
+        assert_eq!(result.unwrap().seq().seq_num_, expected_state.0 + 1);
+        let order_book = order_book_collection
+            .get_book_by_symbol(&String::from("REL"))
+            .unwrap();
+        assert_eq!(
+            order_book
+                .get_order(&String::from("7"))
+                .unwrap()
+                .entry_time_,
+            1_090
+        );
+        drop(order_book_collection);
+
+        //the new event is appended after the replayed ones
+        let mut order_book_collection = MatchingEngine::with_clock(registry(), clock);
+        let replayed_records = order_book_collection
+            .open_journal(&journal_path, FsyncPolicy::Never)
+            .unwrap();
+        assert_eq!(replayed_records, 11);
+        let _ = std::fs::remove_file(&journal_path);
+    }
+
+    #[test]
+    fn failed_journal_write_leaves_no_partial_record() {
+        let journal_path = std::env::temp_dir().join(format!(
+            "matching_engine_short_write_{}.log",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&journal_path);
+        let order = |id: &str| Order {
+            id_: String::from(id),
+            price_: px(100.0),
+            symbol_: String::from("REL"),
+            qty_: Quantity(100),
+            side_: OrderSide::Buy,
+            type_: OrderType::Limit,
+            ..Default::default()
+        };
+
+        let clock = Arc::new(ManualClock::new(1_000));
+        let mut order_book_collection =
+            MatchingEngine::with_clock(test_registry(vec![test_instrument("REL")]), clock.clone());
+        order_book_collection
+            .open_journal(&journal_path, FsyncPolicy::EveryRecord)
+            .unwrap();
+        let result = process_event(EventType::New(order("1")), &mut order_book_collection);
+        validate_result(&result, 0, px(0.0), None);
+
+        //disk fills up in the middle of the record
+        order_book_collection.journal_.as_mut().unwrap().fail_after_ = Some(6);
+        let result = process_event(EventType::New(order("2")), &mut order_book_collection);
+        assert!(matches!(
+            result.unwrap_err().reason_,
+            EngineError::JournalWriteFailed { .. }
+        ));
+
+        clock.advance(10);
+        let result = process_event(EventType::New(order("3")), &mut order_book_collection);
+        validate_result(&result, 0, px(0.0), None);
+        assert_eq!(
+            queue_order_ids(&mut order_book_collection, OrderSide::Buy, px(100.0)),
+            vec!["1", "3"]
+        );
+        let expected_state = engine_state(&order_book_collection);
+        drop(order_book_collection);
+
+        //the record written after the failure is read back
+        let mut order_book_collection =
+            MatchingEngine::with_clock(test_registry(vec![test_instrument("REL")]), clock);
+        let replayed_records = order_book_collection
+            .open_journal(&journal_path, FsyncPolicy::Never)
+            .unwrap();
+        assert_eq!(replayed_records, 2);
+        assert_eq!(engine_state(&order_book_collection), expected_state);
+        let _ = std::fs::remove_file(&journal_path);
+    }
+
+    #[test]
+    fn snapshot_and_journal_tail_restore_the_books() {
+        let file_path = |name: &str| {
+            std::env::temp_dir().join(format!("matching_engine_{name}_{}", std::process::id()))
+        };
+        let journal_path = file_path("snapshot_test.log");
+        let snapshot_path = file_path("snapshot_test.snap");
+        let _ = std::fs::remove_file(&journal_path);
+        let registry = || test_registry(vec![test_instrument("REL"), test_instrument("TCS")]);
+        let order = |id: &str, symbol: &str, side: OrderSide, qty: u64, price: f64| Order {
+            id_: String::from(id),
+            price_: px(price),
+            symbol_: String::from(symbol),
+            qty_: Quantity(qty),
+            side_: side,
+            type_: OrderType::Limit,
+            ..Default::default()
+        };
+
+        let clock = Arc::new(ManualClock::new(1_000));
+        let mut order_book_collection = MatchingEngine::with_clock(registry(), clock.clone());
+        order_book_collection.set_self_match_policy(SelfMatchPolicy::DecrementBoth);
+        order_book_collection
+            .open_journal(&journal_path, FsyncPolicy::Never)
+            .unwrap();
+        let events_before_snapshot = vec![
+            EventType::New(Order {
+                display_qty_: Some(Quantity(20)),
+                client_id_: Some(String::from("C1")),
+                ..order("1", "REL", OrderSide::Sell, 100, 101.0)
+            }),
+            EventType::New(order("2", "REL", OrderSide::Sell, 100, 101.0)),
+            EventType::New(order("3", "REL", OrderSide::Buy, 30, 101.0)),
+            EventType::New(Order {
+                type_: OrderType::Stop,
+                stop_price_: px(102.0),
+                ..order("4", "REL", OrderSide::Buy, 50, 0.0)
+            }),
+            EventType::New(order("5", "TCS", OrderSide::Buy, 100, 99.0)),
+            EventType::Adm(AdminRequest {
+                symbol_: String::from("TCS"),
+                trading_state_: TradingState::PreOpen,
+            }),
+        ];
+        for event in events_before_snapshot {
+            clock.advance(10);
+            let _ = process_event(event, &mut order_book_collection);
+        }
+        order_book_collection
+            .write_snapshot(&snapshot_path)
+            .unwrap();
+
+        let events_after_snapshot = vec![
+            EventType::New(order("6", "REL", OrderSide::Buy, 50, 100.0)),
+            EventType::New(order("7", "REL", OrderSide::Buy, 60, 101.0)),
+            EventType::Cxl(CancelRequest {
+                id_: String::from("6"),
+            }),
+            EventType::New(order("8", "TCS", OrderSide::Sell, 100, 99.0)),
+        ];
+        for event in events_after_snapshot {
+            clock.advance(10);
+            let _ = process_event(event, &mut order_book_collection);
+        }
+        let expected_state = engine_state(&order_book_collection);
+        let expected_symbols = order_book_collection.symbol_by_order_id_.clone();
+        order_book_collection
+            .write_snapshot(&snapshot_path.with_extension("expected"))
+            .unwrap();
+        drop(order_book_collection);
+
+        //only the 4 records after the snapshot are replayed
+        let mut order_book_collection =
+            MatchingEngine::with_clock(registry(), Arc::new(ManualClock::new(0)));
+        order_book_collection
+            .restore_snapshot(&snapshot_path)
+            .unwrap();
+        let replayed_records = order_book_collection
+            .open_journal(&journal_path, FsyncPolicy::Never)
+            .unwrap();
+        assert_eq!(replayed_records, 4);
+        assert_eq!(engine_state(&order_book_collection), expected_state);
+        assert_eq!(order_book_collection.symbol_by_order_id_, expected_symbols);
+        //same books, attributes, stops and counters give the same snapshot
+        order_book_collection
+            .write_snapshot(&snapshot_path)
+            .unwrap();
+        assert_eq!(
+            std::fs::read(&snapshot_path).unwrap(),
+            std::fs::read(snapshot_path.with_extension("expected")).unwrap()
+        );
+        let order_book = order_book_collection
+            .get_book_by_symbol(&String::from("REL"))
+            .unwrap();
+        assert_eq!(order_book.stop_book_.orders().count(), 1);
+        assert_eq!(
+            order_book.self_match_policy_,
+            SelfMatchPolicy::DecrementBoth
+        );
+
+        //unknown versions are refused instead of being misread
+        let mut snapshot = std::fs::read(&snapshot_path).unwrap();
+        snapshot[4] = 99;
+        std::fs::write(&snapshot_path, snapshot).unwrap();
+        let mut order_book_collection = MatchingEngine::new(registry());
+        let error = order_book_collection
+            .restore_snapshot(&snapshot_path)
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+
+        for path in [
+            journal_path,
+            snapshot_path.with_extension("expected"),
+            snapshot_path,
+        ] {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    #[test]
+    fn bbo_is_published_when_top_of_book_moves() {
+        let mut order_book_collection = test_engine();
+        let bbo_receiver = order_book_collection.subscribe_bbo();
+        let symbol = String::from("REL");
+        let bbo = |seq_num: u64, bid: Option<(f64, u64, usize)>, ask: Option<(f64, u64, usize)>| {
+            let level = |(price, qty, order_count): (f64, u64, usize)| DepthLevel {
+                price_: px(price),
+                qty_: Quantity(qty),
+                order_count_: order_count,
+            };
+            BboUpdate {
+                seq_: EventSeq {
+                    seq_num_: seq_num,
+                    symbol_seq_num_: seq_num,
+                },
+                symbol_: symbol.to_owned(),
+                bid_: bid.map(level),
+                ask_: ask.map(level),
+                trading_state_: TradingState::Open,
+            }
+        };
+        let order = |id: &str, side: OrderSide, qty: u64, price: f64| Order {
+            id_: String::from(id),
+            price_: px(price),
+            symbol_: String::from("REL"),
+            qty_: Quantity(qty),
+            side_: side,
+            type_: OrderType::Limit,
+            ..Default::default()
+        };
+
+        //current top of every book on subscription
+        let initial_symbols: Vec<String> = bbo_receiver
+            .try_iter()
+            .map(|bbo_update| bbo_update.symbol_)
+            .collect();
+        assert_eq!(initial_symbols, vec!["REL", "TCS"]);
+
+        let _ = process_event(
+            EventType::New(order("1", OrderSide::Sell, 100, 101.0)),
+            &mut order_book_collection,
+        );
+        assert_eq!(
+            bbo_receiver.try_recv(),
+            Ok(bbo(1, None, Some((101.0, 100, 1))))
+        );
+        let _ = process_event(
+            EventType::New(order("2", OrderSide::Sell, 50, 101.0)),
+            &mut order_book_collection,
+        );
+        assert_eq!(
+            bbo_receiver.try_recv(),
+            Ok(bbo(2, None, Some((101.0, 150, 2))))
+        );
+
+        //behind the top, nothing to publish
+        let _ = process_event(
+            EventType::New(order("3", OrderSide::Sell, 10, 102.0)),
+            &mut order_book_collection,
+        );
+        assert!(bbo_receiver.try_recv().is_err());
+
+        //one update per event, after the ack and both fills
+        let _ = process_event(
+            EventType::New(order("4", OrderSide::Buy, 120, 101.0)),
+            &mut order_book_collection,
+        );
+        assert_eq!(
+            bbo_receiver.try_recv(),
+            Ok(bbo(6, None, Some((101.0, 30, 1))))
+        );
+        assert!(bbo_receiver.try_recv().is_err());
+
+        let _ = process_event(
+            EventType::New(order("5", OrderSide::Buy, 10, 99.0)),
+            &mut order_book_collection,
+        );
+        assert_eq!(
+            bbo_receiver.try_recv(),
+            Ok(bbo(7, Some((99.0, 10, 1)), Some((101.0, 30, 1))))
+        );
+        let replace_request = ReplaceRequest {
+            id_: String::from("5"),
+            symbol_: String::from("REL"),
+            side_: OrderSide::Buy,
+            qty_: Quantity(10),
+            price_: px(100.0),
+        };
+        let _ = process_event(EventType::Rpl(replace_request), &mut order_book_collection);
+        assert_eq!(
+            bbo_receiver.try_recv(),
+            Ok(bbo(8, Some((100.0, 10, 1)), Some((101.0, 30, 1))))
+        );
+        let cancel_request = CancelRequest {
+            id_: String::from("2"),
+        };
+        let _ = process_event(EventType::Cxl(cancel_request), &mut order_book_collection);
+        assert_eq!(
+            bbo_receiver.try_recv(),
+            Ok(bbo(9, Some((100.0, 10, 1)), Some((102.0, 10, 1))))
+        );
+
+        //a dropped receiver unsubscribes
+        drop(bbo_receiver);
+        let _ = process_event(
+            EventType::New(order("6", OrderSide::Buy, 10, 100.5)),
+            &mut order_book_collection,
+        );
+        assert!(order_book_collection.bbo_subscribers_.is_empty());
+    }
+
+    #[test]
+    fn level_displayed_qty_follows_every_book_change() {
+        let mut order_book_collection = test_engine();
+        let symbol = String::from("REL");
+        let assert_displayed_qty = |engine: &MatchingEngine| {
+            let order_book = &engine.order_book_by_symbol_[&symbol];
+            for level in order_book.bids_.values().chain(order_book.asks_.values()) {
+                let mut displayed_qty = Quantity::ZERO;
+                for order in level.orders_.values() {
+                    displayed_qty += order.displayed_qty();
+                }
+                assert_eq!(level.displayed_qty(), displayed_qty);
+            }
+        };
+
+        for (id, qty, display_qty) in [("1", 100, Some(30)), ("2", 50, None)] {
+            let order = Order {
+                id_: String::from(id),
+                price_: px(100.0),
+                symbol_: symbol.to_owned(),
+                qty_: Quantity(qty),
+                side_: OrderSide::Sell,
+                type_: OrderType::Limit,
+                display_qty_: display_qty.map(Quantity),
+                ..Default::default()
+            };
+            let result = process_event(EventType::New(order), &mut order_book_collection);
+            validate_result(&result, 0, px(0.0), None);
+        }
+        assert_eq!(
+            order_book_collection.depth(&symbol, OrderSide::Sell, 1)[0].qty_,
+            Quantity(80)
+        );
+
+        //peak of the iceberg is consumed and replenished, order 2 is partially filled
+        let order = Order {
+            id_: String::from("3"),
+            price_: px(100.0),
+            symbol_: symbol.to_owned(),
+            qty_: Quantity(60),
+            side_: OrderSide::Buy,
+            type_: OrderType::Limit,
+            ..Default::default()
+        };
+        let _ = process_event(EventType::New(order), &mut order_book_collection);
+        assert_displayed_qty(&order_book_collection);
+
+        let replace_request = ReplaceRequest {
+            id_: String::from("2"),
+            symbol_: symbol.to_owned(),
+            side_: OrderSide::Sell,
+            qty_: Quantity(10),
+            price_: px(100.0),
+        };
+        let result = process_event(EventType::Rpl(replace_request), &mut order_book_collection);
+        assert!(result.is_ok());
+        assert_displayed_qty(&order_book_collection);
+
+        let cancel_request = CancelRequest {
+            id_: String::from("1"),
+        };
+        let result = process_event(EventType::Cxl(cancel_request), &mut order_book_collection);
+        assert!(result.is_ok());
+        assert_displayed_qty(&order_book_collection);
+        assert_eq!(
+            order_book_collection.depth(&symbol, OrderSide::Sell, 1)[0].qty_,
+            Quantity(10)
+        );
+    }
+
+    #[test]
+    fn trading_state_changes_are_published_to_market_data() {
+        let mut order_book_collection = test_engine();
+        let symbol = String::from("REL");
+        let bbo_receiver = order_book_collection.subscribe_bbo();
+        let depth_receiver = order_book_collection.subscribe_depth(5);
+        assert_eq!(bbo_receiver.try_iter().count(), 2);
+        assert_eq!(depth_receiver.try_iter().count(), 2);
+
+        let admin_request = AdminRequest {
+            symbol_: symbol.to_owned(),
+            trading_state_: TradingState::Halted,
+        };
+        let result = process_event(EventType::Adm(admin_request), &mut order_book_collection);
+        let trading_state_change = result.unwrap().trading_state_change().unwrap().clone();
+
+        //the top of book did not move, the update carries the new state
+        let bbo_update = bbo_receiver.try_recv().unwrap();
+        assert_eq!(bbo_update.trading_state_, TradingState::Halted);
+        assert_eq!(bbo_update.seq_, trading_state_change.seq_);
+        assert!(bbo_update.bid_.is_none() && bbo_update.ask_.is_none());
+
+        assert_eq!(
+            depth_receiver.try_recv(),
+            Ok(DepthMessage::StateChange(TradingStateChange {
+                seq_: EventSeq {
+                    seq_num_: 2,
+                    symbol_seq_num_: 2,
+                },
+                symbol_: symbol.to_owned(),
+                previous_state_: TradingState::Open,
+                trading_state_: TradingState::Halted,
+            }))
+        );
+        assert!(bbo_receiver.try_recv().is_err());
+        assert!(depth_receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn depth_is_published_as_snapshot_then_level_updates() {
+        let mut order_book_collection = test_engine();
+        let depth_receiver = order_book_collection.subscribe_depth(2);
+        let symbol = String::from("REL");
+        let level = |price: f64, qty: u64, order_count: usize| DepthLevel {
+            price_: px(price),
+            qty_: Quantity(qty),
+            order_count_: order_count,
+        };
+        let order = |id: &str, side: OrderSide, qty: u64, price: f64| Order {
+            id_: String::from(id),
+            price_: px(price),
+            symbol_: String::from("REL"),
+            qty_: Quantity(qty),
+            side_: side,
+            type_: OrderType::Limit,
+            ..Default::default()
+        };
+        //messages of one event, without the sequence numbers
+        let updates = || -> Vec<(&str, OrderSide, Price, Option<DepthLevel>)> {
+            depth_receiver
+                .try_iter()
+                .map(|depth_message| match depth_message {
+                    DepthMessage::AddLevel { side_, level_, .. } => {
+                        ("add", side_, level_.price_, Some(level_))
+                    }
+                    DepthMessage::ChangeLevel { side_, level_, .. } => {
+                        ("change", side_, level_.price_, Some(level_))
+                    }
+                    DepthMessage::DeleteLevel { side_, price_, .. } => {
+                        ("delete", side_, price_, None)
+                    }
+                    depth_message => panic!("unexpected {:?}", depth_message),
+                })
+                .collect()
+        };
+
+        //a snapshot of every book on subscription
+        let snapshots: Vec<DepthMessage> = depth_receiver.try_iter().collect();
+        assert_eq!(snapshots.len(), 2);
+        assert_eq!(
+            snapshots[0],
+            DepthMessage::Snapshot {
+                seq_: EventSeq::default(),
+                symbol_: symbol.to_owned(),
+                trading_state_: TradingState::Open,
+                bids_: Vec::new(),
+                asks_: Vec::new(),
+            }
+        );
+
+        let _ = process_event(
+            EventType::New(order("1", OrderSide::Sell, 100, 101.0)),
+            &mut order_book_collection,
+        );
+        assert_eq!(
+            depth_receiver.try_recv(),
+            Ok(DepthMessage::AddLevel {
+                seq_: EventSeq {
+                    seq_num_: 1,
+                    symbol_seq_num_: 1,
+                },
+                symbol_: symbol.to_owned(),
+                side_: OrderSide::Sell,
+                level_: level(101.0, 100, 1),
+            })
+        );
+        let _ = process_event(
+            EventType::New(order("2", OrderSide::Sell, 50, 101.0)),
+            &mut order_book_collection,
+        );
+        assert_eq!(
+            updates(),
+            vec![(
+                "change",
+                OrderSide::Sell,
+                px(101.0),
+                Some(level(101.0, 150, 2))
+            )]
+        );
+        let _ = process_event(
+            EventType::New(order("3", OrderSide::Sell, 10, 102.0)),
+            &mut order_book_collection,
+        );
+        assert_eq!(
+            updates(),
+            vec![("add", OrderSide::Sell, px(102.0), Some(level(102.0, 10, 1)))]
+        );
+
+        //beyond the depth of the subscription, nothing to publish
+        let _ = process_event(
+            EventType::New(order("4", OrderSide::Sell, 10, 103.0)),
+            &mut order_book_collection,
+        );
+        assert!(updates().is_empty());
+
+        //the best level trades away and the next one moves into the depth
+        let _ = process_event(
+            EventType::New(order("5", OrderSide::Buy, 150, 101.0)),
+            &mut order_book_collection,
+        );
+        assert_eq!(
+            updates(),
+            vec![
+                ("delete", OrderSide::Sell, px(101.0), None),
+                ("add", OrderSide::Sell, px(103.0), Some(level(103.0, 10, 1))),
+            ]
+        );
+
+        let cancel_request = CancelRequest {
+            id_: String::from("3"),
+        };
+        let _ = process_event(EventType::Cxl(cancel_request), &mut order_book_collection);
+        assert_eq!(
+            updates(),
+            vec![("delete", OrderSide::Sell, px(102.0), None)]
+        );
+
+        //a better bid pushes the worst one out
+        let _ = process_event(
+            EventType::New(order("6", OrderSide::Buy, 10, 99.0)),
+            &mut order_book_collection,
+        );
+        let _ = process_event(
+            EventType::New(order("7", OrderSide::Buy, 20, 98.0)),
+            &mut order_book_collection,
+        );
+        let _ = process_event(
+            EventType::New(order("8", OrderSide::Buy, 30, 100.0)),
+            &mut order_book_collection,
+        );
+        assert_eq!(
+            updates(),
+            vec![
+                ("add", OrderSide::Buy, px(99.0), Some(level(99.0, 10, 1))),
+                ("add", OrderSide::Buy, px(98.0), Some(level(98.0, 20, 1))),
+                ("delete", OrderSide::Buy, px(98.0), None),
+                ("add", OrderSide::Buy, px(100.0), Some(level(100.0, 30, 1))),
+            ]
+        );
+
+        //a dropped receiver unsubscribes
+        drop(depth_receiver);
+        let _ = process_event(
+            EventType::New(order("9", OrderSide::Buy, 10, 100.5)),
+            &mut order_book_collection,
+        );
+        assert!(order_book_collection.depth_subscribers_.is_empty());
     }
 }
\ No newline at end of file
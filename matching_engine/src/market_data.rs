@@ -0,0 +1,281 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use msg::order::*;
+
+use crate::{DepthLevel, EventSeq, MatchingEngine, OrderBook, TradingStateChange};
+
+//Best bid and offer of one symbol, None is an empty side. seq_ is the last engine output the
+//update reflects, so it can be lined up with the fills and acks of the same event.
+//Published as well when the trading state of the symbol changes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BboUpdate {
+    pub seq_: EventSeq,
+    pub symbol_: String,
+    pub bid_: Option<DepthLevel>,
+    pub ask_: Option<DepthLevel>,
+    pub trading_state_: TradingState,
+}
+
+//Market by price feed of one symbol. A subscriber gets a Snapshot of the best levels of every
+//book, then the level updates that keep it in line with the book. Levels beyond the depth of the
+//subscription are not published, a level that moves into it is added and one pushed out deleted.
+//Every trading state change of the symbol is published before the level updates of its event.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DepthMessage {
+    Snapshot {
+        seq_: EventSeq,
+        symbol_: String,
+        trading_state_: TradingState,
+        //best price first
+        bids_: Vec<DepthLevel>,
+        asks_: Vec<DepthLevel>,
+    },
+    AddLevel {
+        seq_: EventSeq,
+        symbol_: String,
+        side_: OrderSide,
+        level_: DepthLevel,
+    },
+    ChangeLevel {
+        seq_: EventSeq,
+        symbol_: String,
+        side_: OrderSide,
+        level_: DepthLevel,
+    },
+    DeleteLevel {
+        seq_: EventSeq,
+        symbol_: String,
+        side_: OrderSide,
+        price_: Price,
+    },
+    StateChange(TradingStateChange),
+}
+
+//Levels last sent to one depth subscriber, bids then asks by symbol
+#[derive(Debug)]
+pub(crate) struct DepthSubscriber {
+    sender_: Sender<DepthMessage>,
+    max_levels_: usize,
+    published_: HashMap<String, (Vec<DepthLevel>, Vec<DepthLevel>)>,
+}
+
+impl DepthSubscriber {
+    //Add, change and delete messages that turn the published levels of a side into p_levels
+    fn level_updates(
+        &mut self,
+        p_seq: EventSeq,
+        p_symbol: &str,
+        p_side: OrderSide,
+        p_touched: &BTreeSet<Price>,
+        p_levels: &[DepthLevel],
+    ) -> Vec<DepthMessage> {
+        let max_levels = self.max_levels_;
+        let published = self.published_.entry(p_symbol.to_owned()).or_default();
+        let published = match p_side {
+            OrderSide::Buy => &mut published.0,
+            OrderSide::Sell => &mut published.1,
+        };
+
+        //a full subscription does not see the levels behind its worst level
+        if published.len() == max_levels {
+            let beyond_depth = |price: &Price| match (p_side, published.last()) {
+                (_, None) => true,
+                (OrderSide::Buy, Some(worst)) => *price < worst.price_,
+                (OrderSide::Sell, Some(worst)) => *price > worst.price_,
+            };
+            if p_touched.iter().all(beyond_depth) {
+                return Vec::new();
+            }
+        }
+
+        let mut depth_messages = Vec::new();
+        for level in published.iter() {
+            if !p_levels
+                .iter()
+                .any(|current| current.price_ == level.price_)
+            {
+                depth_messages.push(DepthMessage::DeleteLevel {
+                    seq_: p_seq,
+                    symbol_: p_symbol.to_owned(),
+                    side_: p_side,
+                    price_: level.price_,
+                });
+            }
+        }
+        for level in p_levels {
+            match published
+                .iter()
+                .find(|previous| previous.price_ == level.price_)
+            {
+                None => depth_messages.push(DepthMessage::AddLevel {
+                    seq_: p_seq,
+                    symbol_: p_symbol.to_owned(),
+                    side_: p_side,
+                    level_: level.clone(),
+                }),
+                Some(previous) if previous != level => {
+                    depth_messages.push(DepthMessage::ChangeLevel {
+                        seq_: p_seq,
+                        symbol_: p_symbol.to_owned(),
+                        side_: p_side,
+                        level_: level.clone(),
+                    })
+                }
+                Some(_) => {}
+            }
+        }
+        *published = p_levels.to_vec();
+        depth_messages
+    }
+}
+
+impl OrderBook {
+    pub(crate) fn top_of_book(&self) -> (Option<DepthLevel>, Option<DepthLevel>) {
+        (
+            self.depth(OrderSide::Buy, 1).pop(),
+            self.depth(OrderSide::Sell, 1).pop(),
+        )
+    }
+}
+
+impl MatchingEngine {
+    //Every BBO update from now on, it starts with the current top of every book
+    pub fn subscribe_bbo(&mut self) -> Receiver<BboUpdate> {
+        let (sender, receiver) = channel();
+        let mut symbols: Vec<&String> = self.order_book_by_symbol_.keys().collect();
+        symbols.sort();
+        for symbol in symbols {
+            let order_book = &self.order_book_by_symbol_[symbol];
+            let (bid, ask) = order_book.top_of_book();
+            //receiver is still in hand, the send can not fail
+            let _ = sender.send(BboUpdate {
+                seq_: EventSeq {
+                    seq_num_: self.last_seq_num_,
+                    symbol_seq_num_: order_book.last_seq_num_,
+                },
+                symbol_: symbol.to_owned(),
+                bid_: bid,
+                ask_: ask,
+                trading_state_: order_book.trading_state_,
+            });
+        }
+        self.bbo_subscribers_.push(sender);
+        receiver
+    }
+
+    //Called after every event on p_symbol, publishes only when the top of book moved or the
+    //trading state changed. Subscribers that dropped their receiver are forgotten.
+    pub(crate) fn publish_bbo(&mut self, p_symbol: &String, p_state_changed: bool) {
+        let Some(order_book) = self.order_book_by_symbol_.get_mut(p_symbol) else {
+            return;
+        };
+        let top_of_book = order_book.top_of_book();
+        if top_of_book == order_book.published_bbo_ && !p_state_changed {
+            return;
+        }
+        order_book.published_bbo_ = top_of_book.clone();
+
+        let bbo_update = BboUpdate {
+            seq_: EventSeq {
+                seq_num_: self.last_seq_num_,
+                symbol_seq_num_: order_book.last_seq_num_,
+            },
+            symbol_: p_symbol.to_owned(),
+            bid_: top_of_book.0,
+            ask_: top_of_book.1,
+            trading_state_: order_book.trading_state_,
+        };
+        self.bbo_subscribers_
+            .retain(|subscriber| subscriber.send(bbo_update.clone()).is_ok());
+    }
+
+    //Best p_max_levels levels of every book from now on, it starts with a snapshot of each book
+    pub fn subscribe_depth(&mut self, p_max_levels: usize) -> Receiver<DepthMessage> {
+        let (sender, receiver) = channel();
+        let mut subscriber = DepthSubscriber {
+            sender_: sender,
+            max_levels_: p_max_levels,
+            published_: HashMap::new(),
+        };
+        let mut symbols: Vec<&String> = self.order_book_by_symbol_.keys().collect();
+        symbols.sort();
+        for symbol in symbols {
+            let order_book = &self.order_book_by_symbol_[symbol];
+            let bids = order_book.depth(OrderSide::Buy, p_max_levels);
+            let asks = order_book.depth(OrderSide::Sell, p_max_levels);
+            //receiver is still in hand, the send can not fail
+            let _ = subscriber.sender_.send(DepthMessage::Snapshot {
+                seq_: EventSeq {
+                    seq_num_: self.last_seq_num_,
+                    symbol_seq_num_: order_book.last_seq_num_,
+                },
+                symbol_: symbol.to_owned(),
+                trading_state_: order_book.trading_state_,
+                bids_: bids.clone(),
+                asks_: asks.clone(),
+            });
+            subscriber
+                .published_
+                .insert(symbol.to_owned(), (bids, asks));
+        }
+        self.depth_subscribers_.push(subscriber);
+        receiver
+    }
+
+    //Called after every event on p_symbol, sends each subscriber the state changes of the event
+    //and the levels that changed within its depth. Only the levels touched by the event are
+    //looked at.
+    pub(crate) fn publish_depth(
+        &mut self,
+        p_symbol: &String,
+        p_trading_state_changes: &[TradingStateChange],
+    ) {
+        let Some(order_book) = self.order_book_by_symbol_.get_mut(p_symbol) else {
+            return;
+        };
+        let touched_bids = std::mem::take(&mut order_book.touched_bids_);
+        let touched_asks = std::mem::take(&mut order_book.touched_asks_);
+        if touched_bids.is_empty() && touched_asks.is_empty() && p_trading_state_changes.is_empty()
+        {
+            return;
+        }
+        let seq = EventSeq {
+            seq_num_: self.last_seq_num_,
+            symbol_seq_num_: order_book.last_seq_num_,
+        };
+
+        let order_book = &self.order_book_by_symbol_[p_symbol];
+        self.depth_subscribers_.retain_mut(|subscriber| {
+            let mut depth_messages: Vec<DepthMessage> = p_trading_state_changes
+                .iter()
+                .cloned()
+                .map(DepthMessage::StateChange)
+                .collect();
+            for (side, touched) in [
+                (OrderSide::Buy, &touched_bids),
+                (OrderSide::Sell, &touched_asks),
+            ] {
+                if touched.is_empty() {
+                    continue;
+                }
+                let levels = order_book.depth(side, subscriber.max_levels_);
+                depth_messages
+                    .extend(subscriber.level_updates(seq, p_symbol, side, touched, &levels));
+            }
+            depth_messages
+                .into_iter()
+                .all(|depth_message| subscriber.sender_.send(depth_message).is_ok())
+        });
+    }
+
+    //BBO then depth, after every event on p_symbol
+    pub(crate) fn publish_market_data(
+        &mut self,
+        p_symbol: &String,
+        p_trading_state_changes: &[TradingStateChange],
+    ) {
+        self.publish_bbo(p_symbol, !p_trading_state_changes.is_empty());
+        self.publish_depth(p_symbol, p_trading_state_changes);
+    }
+}
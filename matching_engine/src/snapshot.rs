@@ -0,0 +1,246 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+use msg::order::*;
+
+use crate::codec::{invalid_data, Decoder, Encoder};
+use crate::stop_book::StopBook;
+use crate::{Level, MatchingEngine, OrderBook, OrderLocation, SelfMatchPolicy};
+
+const SNAPSHOT_MAGIC: &[u8; 4] = b"SPXS";
+//Bumped on every change of the layout, restore keeps a reader for every older version
+const SNAPSHOT_VERSION: u32 = 1;
+
+//Resting orders and counters of one book, everything else comes from its Instrument
+struct BookSnapshot {
+    symbol_: String,
+    trading_state_: TradingState,
+    last_priority_seq_: u64,
+    last_seq_num_: u64,
+    last_trade_price_: Option<Price>,
+    self_match_policy_: SelfMatchPolicy,
+    //bids from the best price then asks from the best price, each level in queue order
+    orders_: Vec<Order>,
+    last_stop_seq_: u64,
+    stops_: Vec<(u64, Order)>,
+}
+
+fn put_self_match_policy(p_encoder: &mut Encoder, p_policy: SelfMatchPolicy) {
+    p_encoder.put_u8(match p_policy {
+        SelfMatchPolicy::CancelResting => 0,
+        SelfMatchPolicy::CancelAggressor => 1,
+        SelfMatchPolicy::CancelBoth => 2,
+        SelfMatchPolicy::DecrementBoth => 3,
+    });
+}
+
+fn get_self_match_policy(p_decoder: &mut Decoder) -> io::Result<SelfMatchPolicy> {
+    match p_decoder.get_u8()? {
+        0 => Ok(SelfMatchPolicy::CancelResting),
+        1 => Ok(SelfMatchPolicy::CancelAggressor),
+        2 => Ok(SelfMatchPolicy::CancelBoth),
+        3 => Ok(SelfMatchPolicy::DecrementBoth),
+        tag => Err(invalid_data(format!("Unknown self match policy tag {tag}"))),
+    }
+}
+
+impl OrderBook {
+    fn write_snapshot(&self, p_symbol: &str, p_encoder: &mut Encoder) {
+        p_encoder.put_str(p_symbol);
+        p_encoder.put_trading_state(self.trading_state_);
+        p_encoder.put_u64(self.last_priority_seq_);
+        p_encoder.put_u64(self.last_seq_num_);
+        p_encoder.put_option(self.last_trade_price_, Encoder::put_price);
+        put_self_match_policy(p_encoder, self.self_match_policy_);
+
+        let orders: Vec<&Order> = self
+            .bids_
+            .values()
+            .rev()
+            .chain(self.asks_.values())
+            .flat_map(|level| level.orders_.values())
+            .collect();
+        p_encoder.put_u32(orders.len() as u32);
+        for order in orders {
+            p_encoder.put_order(order);
+        }
+
+        p_encoder.put_u64(self.stop_book_.last_stop_seq());
+        let stops: Vec<(u64, &Order)> = self.stop_book_.entries().collect();
+        p_encoder.put_u32(stops.len() as u32);
+        for (stop_seq, order) in stops {
+            p_encoder.put_u64(stop_seq);
+            p_encoder.put_order(order);
+        }
+    }
+
+    //Orders keep the priority_seq_ and peak of the snapshot
+    fn restore_snapshot(&mut self, p_book_snapshot: BookSnapshot) {
+        self.trading_state_ = p_book_snapshot.trading_state_;
+        self.last_priority_seq_ = p_book_snapshot.last_priority_seq_;
+        self.last_seq_num_ = p_book_snapshot.last_seq_num_;
+        self.last_trade_price_ = p_book_snapshot.last_trade_price_;
+        self.self_match_policy_ = p_book_snapshot.self_match_policy_;
+        for order in p_book_snapshot.orders_ {
+            self.order_index_.insert(
+                order.id_.to_owned(),
+                OrderLocation {
+                    side_: order.side_,
+                    price_: order.price_,
+                    priority_seq_: order.priority_seq_,
+                },
+            );
+            self.levels_mut(order.side_)
+                .entry(order.price_)
+                .or_insert_with(|| Level::from_order(&order))
+                .add_order(&order);
+        }
+        self.stop_book_ =
+            StopBook::restored(p_book_snapshot.last_stop_seq_, p_book_snapshot.stops_);
+        self.published_bbo_ = self.top_of_book();
+    }
+}
+
+impl BookSnapshot {
+    fn read_v1(p_decoder: &mut Decoder) -> io::Result<BookSnapshot> {
+        let symbol = p_decoder.get_str()?;
+        let trading_state = p_decoder.get_trading_state()?;
+        let last_priority_seq = p_decoder.get_u64()?;
+        let last_seq_num = p_decoder.get_u64()?;
+        let last_trade_price = p_decoder.get_option(Decoder::get_price)?;
+        let self_match_policy = get_self_match_policy(p_decoder)?;
+
+        let order_count = p_decoder.get_u32()?;
+        let mut orders = Vec::new();
+        for _ in 0..order_count {
+            orders.push(p_decoder.get_order()?);
+        }
+
+        let last_stop_seq = p_decoder.get_u64()?;
+        let stop_count = p_decoder.get_u32()?;
+        let mut stops = Vec::new();
+        for _ in 0..stop_count {
+            let stop_seq = p_decoder.get_u64()?;
+            stops.push((stop_seq, p_decoder.get_order()?));
+        }
+
+        Ok(BookSnapshot {
+            symbol_: symbol,
+            trading_state_: trading_state,
+            last_priority_seq_: last_priority_seq,
+            last_seq_num_: last_seq_num,
+            last_trade_price_: last_trade_price,
+            self_match_policy_: self_match_policy,
+            orders_: orders,
+            last_stop_seq_: last_stop_seq,
+            stops_: stops,
+        })
+    }
+}
+
+impl MatchingEngine {
+    //Consistent image of every book and of the engine counters, taken between two events.
+    //Written next to p_path first and renamed, a crash never leaves a partial snapshot behind.
+    //The journal is synced first, the snapshot never covers records that are not on disk.
+    pub fn write_snapshot(&mut self, p_path: &Path) -> io::Result<()> {
+        if let Some(journal) = self.journal_.as_mut() {
+            journal.sync()?;
+        }
+        let mut encoder = Encoder::new();
+        for byte in SNAPSHOT_MAGIC {
+            encoder.put_u8(*byte);
+        }
+        encoder.put_u32(SNAPSHOT_VERSION);
+        encoder.put_u64(self.last_seq_num_);
+        encoder.put_u64(self.last_trade_id_);
+        encoder.put_u64(self.last_entry_time_);
+        encoder.put_u64(self.journal_records_);
+        put_self_match_policy(&mut encoder, self.self_match_policy_);
+
+        //symbols are sorted so that the same books always give the same snapshot
+        let mut symbols: Vec<&String> = self.order_book_by_symbol_.keys().collect();
+        symbols.sort();
+        encoder.put_u32(symbols.len() as u32);
+        for symbol in symbols {
+            self.order_book_by_symbol_[symbol].write_snapshot(symbol, &mut encoder);
+        }
+
+        let temp_path = p_path.with_extension("tmp");
+        let mut file = File::create(&temp_path)?;
+        file.write_all(&encoder.into_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temp_path, p_path)
+    }
+
+    //Loads a snapshot into a fresh engine created with the same instruments, open_journal
+    //afterwards replays only the journal records that came after the snapshot
+    pub fn restore_snapshot(&mut self, p_path: &Path) -> io::Result<()> {
+        let bytes = fs::read(p_path)?;
+        let mut decoder = Decoder::new(&bytes);
+        let mut magic = [0; 4];
+        for byte in &mut magic {
+            *byte = decoder.get_u8()?;
+        }
+        if &magic != SNAPSHOT_MAGIC {
+            return Err(invalid_data(format!("{p_path:?} is not a snapshot")));
+        }
+        match decoder.get_u32()? {
+            1 => self.restore_v1(&mut decoder)?,
+            version => {
+                return Err(invalid_data(format!(
+                    "Snapshot version {version} is not supported, latest is {SNAPSHOT_VERSION}"
+                )))
+            }
+        }
+        if !decoder.is_empty() {
+            return Err(invalid_data(format!(
+                "Snapshot {p_path:?} has trailing bytes"
+            )));
+        }
+        Ok(())
+    }
+
+    fn restore_v1(&mut self, p_decoder: &mut Decoder) -> io::Result<()> {
+        let last_seq_num = p_decoder.get_u64()?;
+        let last_trade_id = p_decoder.get_u64()?;
+        let last_entry_time = p_decoder.get_u64()?;
+        let journal_records = p_decoder.get_u64()?;
+        let self_match_policy = get_self_match_policy(p_decoder)?;
+
+        let book_count = p_decoder.get_u32()?;
+        let mut book_snapshots = Vec::new();
+        for _ in 0..book_count {
+            let book_snapshot = BookSnapshot::read_v1(p_decoder)?;
+            if !self.contains(&book_snapshot.symbol_) {
+                return Err(invalid_data(format!(
+                    "Snapshot has symbol {} which is not in the instrument registry",
+                    book_snapshot.symbol_
+                )));
+            }
+            book_snapshots.push(book_snapshot);
+        }
+
+        //nothing is changed till the whole snapshot is read
+        self.last_seq_num_ = last_seq_num;
+        self.last_trade_id_ = last_trade_id;
+        self.last_entry_time_ = last_entry_time;
+        self.journal_records_ = journal_records;
+        self.self_match_policy_ = self_match_policy;
+        for book_snapshot in book_snapshots {
+            let symbol = book_snapshot.symbol_.to_owned();
+            for order in book_snapshot
+                .orders_
+                .iter()
+                .chain(book_snapshot.stops_.iter().map(|(_, order)| order))
+            {
+                self.symbol_by_order_id_
+                    .insert(order.id_.to_owned(), symbol.to_owned());
+            }
+            if let Some(order_book) = self.order_book_by_symbol_.get_mut(&symbol) {
+                order_book.restore_snapshot(book_snapshot);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+
+use msg::order::*;
+
+use crate::OrderLocation;
+
+//Non displayed trigger book of one symbol, stop and stop limit orders wait here
+//till a trade prints at or through their stop price.
+#[derive(Debug, Default)]
+pub(crate) struct StopBook {
+    //Keyed by (stop price, arrival seq)
+    buy_stops_: BTreeMap<(Price, u64), Order>,
+    sell_stops_: BTreeMap<(Price, u64), Order>,
+    stop_index_: HashMap<String, OrderLocation>,
+    last_stop_seq_: u64,
+}
+
+impl StopBook {
+    pub(crate) fn is_triggered(p_order: &Order, p_last_trade_price: Price) -> bool {
+        match p_order.side_ {
+            OrderSide::Buy => p_last_trade_price >= p_order.stop_price_,
+            OrderSide::Sell => p_last_trade_price <= p_order.stop_price_,
+        }
+    }
+
+    pub(crate) fn add_order(&mut self, p_order: &Order) {
+        self.last_stop_seq_ += 1;
+        let key = (p_order.stop_price_, self.last_stop_seq_);
+        self.stop_index_.insert(
+            p_order.id_.to_owned(),
+            OrderLocation {
+                side_: p_order.side_,
+                price_: p_order.stop_price_,
+                priority_seq_: self.last_stop_seq_,
+            },
+        );
+        self.stops_mut(p_order.side_)
+            .insert(key, p_order.to_owned());
+        println!("Stop order id {:?} added into trigger book", p_order.id_);
+    }
+
+    pub(crate) fn get_order(&self, p_order_id: &String) -> Option<&Order> {
+        let location = self.stop_index_.get(p_order_id)?;
+        let stops = match location.side_ {
+            OrderSide::Buy => &self.buy_stops_,
+            OrderSide::Sell => &self.sell_stops_,
+        };
+        stops.get(&(location.price_, location.priority_seq_))
+    }
+
+    pub(crate) fn remove_order_by_id(&mut self, p_order_id: &String) -> Option<Order> {
+        let location = self.stop_index_.remove(p_order_id)?;
+        self.stops_mut(location.side_)
+            .remove(&(location.price_, location.priority_seq_))
+    }
+
+    pub(crate) fn orders(&self) -> impl Iterator<Item = &Order> {
+        self.buy_stops_.values().chain(self.sell_stops_.values())
+    }
+
+    //Stops with their arrival seq, buy stops first, written to snapshots
+    pub(crate) fn entries(&self) -> impl Iterator<Item = (u64, &Order)> {
+        self.buy_stops_
+            .iter()
+            .chain(self.sell_stops_.iter())
+            .map(|((_, stop_seq), order)| (*stop_seq, order))
+    }
+
+    pub(crate) fn last_stop_seq(&self) -> u64 {
+        self.last_stop_seq_
+    }
+
+    //Trigger book of a snapshot, every stop keeps its arrival seq
+    pub(crate) fn restored(p_last_stop_seq: u64, p_stops: Vec<(u64, Order)>) -> StopBook {
+        let mut stop_book = StopBook {
+            last_stop_seq_: p_last_stop_seq,
+            ..Default::default()
+        };
+        for (stop_seq, order) in p_stops {
+            stop_book.put_back(stop_seq, order);
+        }
+        stop_book
+    }
+
+    //Stop taken out by take_triggered that could not be injected, it keeps its arrival seq
+    pub(crate) fn put_back(&mut self, p_stop_seq: u64, p_order: Order) {
+        self.stop_index_.insert(
+            p_order.id_.to_owned(),
+            OrderLocation {
+                side_: p_order.side_,
+                price_: p_order.stop_price_,
+                priority_seq_: p_stop_seq,
+            },
+        );
+        self.stops_mut(p_order.side_)
+            .insert((p_order.stop_price_, p_stop_seq), p_order);
+    }
+
+    //Same convention as the book: a qty reduction at the same price keeps the trigger priority,
+    //any other change takes a new arrival seq. The stop price can not be changed.
+    pub(crate) fn amend_order(&mut self, p_order: &Order) -> bool {
+        let Some(location) = self.stop_index_.get(&p_order.id_).copied() else {
+            return false;
+        };
+        let key = (location.price_, location.priority_seq_);
+        let Some(stop_order) = self.stops_mut(location.side_).get_mut(&key) else {
+            return false;
+        };
+        if stop_order.price_ == p_order.price_ && p_order.qty_ <= stop_order.qty_ {
+            stop_order.qty_ = p_order.qty_;
+            println!("Stop order id {:?} amended in place", p_order.id_);
+            return true;
+        }
+        self.remove_order_by_id(&p_order.id_);
+        self.add_order(p_order);
+        true
+    }
+
+    //Removes and returns the stops triggered by the trades printed between p_lowest_price and
+    //p_highest_price, in trigger priority: buy stops from the lowest stop price, sell stops from
+    //the highest stop price, and arrival order between stops with the same stop price.
+    //Every stop comes with its arrival seq.
+    pub(crate) fn take_triggered(
+        &mut self,
+        p_lowest_price: Price,
+        p_highest_price: Price,
+    ) -> Vec<(u64, Order)> {
+        let mut triggered_keys: Vec<(OrderSide, (Price, u64))> = self
+            .buy_stops_
+            .range(..=(p_highest_price, u64::MAX))
+            .map(|(key, _)| (OrderSide::Buy, *key))
+            .collect();
+
+        let mut sell_keys: Vec<(Price, u64)> = self
+            .sell_stops_
+            .range((p_lowest_price, 0)..)
+            .map(|(key, _)| *key)
+            .collect();
+        sell_keys.sort_by(|left, right| right.0.cmp(&left.0).then(left.1.cmp(&right.1)));
+        triggered_keys.extend(sell_keys.into_iter().map(|key| (OrderSide::Sell, key)));
+
+        let mut triggered_orders = Vec::new();
+        for (side, key) in triggered_keys {
+            if let Some(order) = self.stops_mut(side).remove(&key) {
+                self.stop_index_.remove(&order.id_);
+                triggered_orders.push((key.1, order));
+            }
+        }
+        triggered_orders
+    }
+
+    fn stops_mut(&mut self, p_side: OrderSide) -> &mut BTreeMap<(Price, u64), Order> {
+        match p_side {
+            OrderSide::Buy => &mut self.buy_stops_,
+            OrderSide::Sell => &mut self.sell_stops_,
+        }
+    }
+}
@@ -1,6 +1,75 @@
-use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, AddAssign, Sub, SubAssign};
 use std::time::SystemTime;
 
+//Price in integer ticks, a tick is 10^-scale where scale is the number of
+//decimal places of the instrument. Ticks are exact so they are safe to use as level keys.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Price(pub i64);
+
+impl Price {
+    //Converts a decimal price using the instrument scale, None if it is not a finite price
+    pub fn from_f64(p_value: f64, p_scale: u32) -> Option<Price> {
+        let ticks = (p_value * 10f64.powi(p_scale as i32)).round();
+        if !ticks.is_finite() || ticks < i64::MIN as f64 || ticks > i64::MAX as f64 {
+            return None;
+        }
+        Some(Price(ticks as i64))
+    }
+
+    pub fn to_f64(self, p_scale: u32) -> f64 {
+        self.0 as f64 / 10f64.powi(p_scale as i32)
+    }
+
+    //Exact notional in ticks * qty units
+    pub fn notional(self, p_qty: Quantity) -> i128 {
+        self.0 as i128 * p_qty.0 as i128
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Quantity(pub u64);
+
+impl Quantity {
+    pub const ZERO: Quantity = Quantity(0);
+
+    pub fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Add for Quantity {
+    type Output = Quantity;
+    fn add(self, p_other: Quantity) -> Quantity {
+        Quantity(self.0 + p_other.0)
+    }
+}
+
+impl AddAssign for Quantity {
+    fn add_assign(&mut self, p_other: Quantity) {
+        self.0 += p_other.0;
+    }
+}
+
+impl Sub for Quantity {
+    type Output = Quantity;
+    fn sub(self, p_other: Quantity) -> Quantity {
+        Quantity(self.0 - p_other.0)
+    }
+}
+
+impl SubAssign for Quantity {
+    fn sub_assign(&mut self, p_other: Quantity) {
+        self.0 -= p_other.0;
+    }
+}
+
 //Order
 // TODO:: Find a way to attach these enums to the Order struct only and not a global enums
 // TODO:: Fix the string types in this project, currently all of them are owned strings
@@ -11,48 +80,180 @@ pub enum OrderSide {
     Sell,
 }
 
+impl OrderSide {
+    pub fn opposite(self) -> OrderSide {
+        match self {
+            OrderSide::Buy => OrderSide::Sell,
+            OrderSide::Sell => OrderSide::Buy,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Copy)]
 pub enum OrderType {
     Mkt,
     Limit,
+    // Becomes a Mkt order once a trade prints at or through stop_price_
+    Stop,
+    // Becomes a Limit order at price_ once a trade prints at or through stop_price_
+    StopLimit,
 }
 
-#[derive(Clone, Debug, Copy)]
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Default)]
+pub enum TimeInForce {
+    // Rests till the end of the trading day
+    Day,
+    // Rests till it is filled or cancelled
+    #[default]
+    Gtc,
+    // Immediate or cancel, unfilled qty is cancelled
+    Ioc,
+    // Fill or kill, fully filled at once or cancelled without any fill
+    Fok,
+    // Rests till the given expiry time
+    Gtd(SystemTime),
+}
+
+#[derive(Clone, Debug)]
 pub enum EventType {
-    New,
-    Rpl,
-    Cxl,
+    New(Order),
+    Rpl(ReplaceRequest),
+    Cxl(CancelRequest),
+    Adm(AdminRequest),
+}
+
+//Trading phase of one symbol
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Default)]
+pub enum TradingState {
+    // Call phase, orders are accepted without matching and uncrossed on open
+    PreOpen,
+    #[default]
+    Open,
+    // Only cancels are accepted
+    Halted,
+    // Every request is rejected
+    Closed,
+}
+
+//What happens to a post-only order that would take liquidity on entry
+#[derive(Clone, Debug, Copy, PartialEq, Eq)]
+pub enum PostOnly {
+    Reject,
+    // Re-priced one tick behind the opposite best, it rests without trading
+    Reprice,
 }
 
 #[derive(Clone, Debug)]
 pub struct Order {
     pub id_: String,
     pub symbol_: String,
-    pub qty_: i32,
-    pub price_: f32,
-    pub entry_time_: SystemTime,
+    pub qty_: Quantity,
+    pub price_: Price,
+    // Monotonic nanoseconds stamped by the engine clock when the order enters the engine
+    pub entry_time_: u64,
     pub side_: OrderSide,
     pub type_: OrderType,
+    // Market order protection (market-to-limit), the sweep never goes beyond this price
+    pub protection_price_: Option<Price>,
+    // Time priority in the book, assigned by the engine when the order joins a level queue
+    pub priority_seq_: u64,
+    pub time_in_force_: TimeInForce,
+    // Trigger price of Stop and StopLimit orders
+    pub stop_price_: Price,
+    // Peak size of iceberg orders, None displays the whole qty
+    pub display_qty_: Option<Quantity>,
+    // Displayed part of a resting iceberg order, replenished by the engine from the hidden reserve
+    pub peak_qty_: Quantity,
+    // Maker-only instruction, None for a regular order
+    pub post_only_: Option<PostOnly>,
+    // Participant/client that owns the order, orders of the same owner never trade with each other
+    pub client_id_: Option<String>,
 }
 
-impl PartialOrd for Order {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
+impl Default for Order {
+    fn default() -> Self {
+        Order {
+            id_: String::new(),
+            symbol_: String::new(),
+            qty_: Quantity::ZERO,
+            price_: Price::default(),
+            entry_time_: 0,
+            side_: OrderSide::Buy,
+            type_: OrderType::Limit,
+            protection_price_: None,
+            priority_seq_: 0,
+            time_in_force_: TimeInForce::Gtc,
+            stop_price_: Price::default(),
+            display_qty_: None,
+            peak_qty_: Quantity::ZERO,
+            post_only_: None,
+            client_id_: None,
+        }
     }
 }
 
-impl PartialEq for Order {
-    fn eq(&self, other: &Self) -> bool {
-        self.cmp(other) == Ordering::Equal
+impl Order {
+    //Qty visible to the market, only the peak for iceberg orders
+    pub fn displayed_qty(&self) -> Quantity {
+        match self.display_qty_ {
+            None => self.qty_,
+            Some(_) => self.peak_qty_,
+        }
     }
 }
 
-impl Ord for Order {
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.entry_time_
-            .partial_cmp(&other.entry_time_)
-            .unwrap_or(Ordering::Equal)
+//Cancel of a resting order, the engine resolves symbol, side and price from the id
+#[derive(Clone, Debug, Default)]
+pub struct CancelRequest {
+    pub id_: String,
+}
+
+//Replace of a resting order, symbol_ and side_ can not be changed and must be the same as
+//the original order. qty_ is the new open qty of the order.
+#[derive(Clone, Debug)]
+pub struct ReplaceRequest {
+    pub id_: String,
+    pub symbol_: String,
+    pub side_: OrderSide,
+    pub qty_: Quantity,
+    pub price_: Price,
+}
+
+//Moves a symbol to another trading state
+#[derive(Clone, Debug)]
+pub struct AdminRequest {
+    pub symbol_: String,
+    pub trading_state_: TradingState,
+}
+
+//Orders are the same order when they have the same id, priority is not part of the identity
+impl PartialEq for Order {
+    fn eq(&self, other: &Self) -> bool {
+        self.id_ == other.id_
     }
 }
 
 impl Eq for Order {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn price_from_f64() {
+        assert_eq!(Price::from_f64(100.1, 2), Some(Price(10010)));
+        assert_eq!(Price::from_f64(100.10000001, 2), Some(Price(10010)));
+        assert_eq!(Price::from_f64(0.3, 4), Some(Price(3000)));
+        assert_eq!(Price::from_f64(-1.25, 2), Some(Price(-125)));
+        assert_eq!(Price::from_f64(f64::NAN, 2), None);
+        assert_eq!(Price::from_f64(f64::INFINITY, 2), None);
+        assert_eq!(Price(10010).to_f64(2), 100.1);
+    }
+
+    #[test]
+    fn notional_is_exact() {
+        let price = Price::from_f64(100.1, 2).unwrap();
+        assert_eq!(price.notional(Quantity(3)), 30030);
+        assert_eq!(Price(i64::MAX).notional(Quantity(2)), i64::MAX as i128 * 2);
+    }
+}